@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE subscription_attempts (
+  ip TEXT NOT NULL,
+  email TEXT NOT NULL,
+  attempted_at timestamptz NOT NULL
+);
+
+CREATE INDEX subscription_attempts_ip_idx ON subscription_attempts (ip, attempted_at);
+CREATE INDEX subscription_attempts_email_idx ON subscription_attempts (email, attempted_at);
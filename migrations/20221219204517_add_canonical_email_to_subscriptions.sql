@@ -0,0 +1,46 @@
+-- Add migration script here
+ALTER TABLE subscriptions ADD COLUMN canonical_email TEXT NULL;
+UPDATE subscriptions SET canonical_email = lower(email);
+
+-- Merge subscribers whose addresses only differ by case.
+-- The survivor is the confirmed one if there is any, otherwise the oldest.
+CREATE TEMPORARY TABLE duplicate_subscriptions AS
+SELECT id, survivor_id
+FROM (
+  SELECT
+    id,
+    first_value(id) OVER (
+      PARTITION BY canonical_email
+      ORDER BY status = 'confirmed' DESC, subscribed_at, id
+    ) AS survivor_id
+  FROM subscriptions
+) ranked
+WHERE id <> survivor_id;
+
+UPDATE subscription_tokens t
+SET subscriber_id = d.survivor_id
+FROM duplicate_subscriptions d
+WHERE t.subscriber_id = d.id;
+
+UPDATE issue_opens o
+SET subscriber_id = d.survivor_id
+FROM duplicate_subscriptions d
+WHERE o.subscriber_id = d.id;
+
+UPDATE issue_clicks c
+SET subscriber_id = d.survivor_id
+FROM duplicate_subscriptions d
+WHERE c.subscriber_id = d.id;
+
+-- If a duplicate was confirmed so was its survivor, which has its own deliveries queued.
+DELETE FROM issue_delivery_queue q
+USING subscriptions s, duplicate_subscriptions d
+WHERE s.id = d.id AND q.subscriber_email = s.email;
+
+DELETE FROM subscriptions
+WHERE id IN (SELECT id FROM duplicate_subscriptions);
+
+DROP TABLE duplicate_subscriptions;
+
+ALTER TABLE subscriptions ALTER COLUMN canonical_email SET NOT NULL;
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_canonical_email_key UNIQUE (canonical_email);
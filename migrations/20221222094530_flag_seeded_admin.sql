@@ -0,0 +1,10 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN must_change_password BOOLEAN NOT NULL DEFAULT false;
+
+-- The account from 20221128232109_seed_user.sql has a published password.
+-- If nobody has changed it yet, make sure whoever logs in next has to.
+UPDATE users
+SET must_change_password = true
+WHERE
+  user_id = '58adc858-88bd-4ec6-aecc-800367858fe9' AND
+  password_hash = '$argon2id$v=19$m=15000,t=2,p=1$WQFskHQ3dhvvIYpB5KVVlw$HBDCcv999PzA9Q3UJfn+SsGJWwFWR5IJkVZVwwg1nA8';
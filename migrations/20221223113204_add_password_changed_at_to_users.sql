@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN password_changed_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,2 @@
+-- The trace of the request that published the issue, carried over to the worker.
+ALTER TABLE issue_delivery_queue ADD COLUMN traceparent TEXT NULL;
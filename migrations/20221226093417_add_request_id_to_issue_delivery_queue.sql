@@ -0,0 +1,2 @@
+-- The id of the request that published the issue, so its deliveries can be found in the worker logs.
+ALTER TABLE issue_delivery_queue ADD COLUMN request_id TEXT NULL;
@@ -0,0 +1,152 @@
+use sha2::{Digest, Sha256};
+
+/// A challenge the subscribe form must solve before we send a confirmation email,
+/// e.g. a proof-of-work puzzle or a third party CAPTCHA.
+#[async_trait::async_trait]
+pub trait ChallengeVerifier: Send + Sync {
+    /// Markup embedded in the subscribe form to let the browser answer the challenge.
+    ///
+    /// Inline scripts must carry `csp_nonce` or the browser refuses to run them.
+    fn form_html(&self, csp_nonce: &str) -> String;
+
+    /// Checks the `challenge_response` submitted alongside `form_token`.
+    async fn verify(
+        &self,
+        form_token: &str,
+        response: &str,
+        remote_ip: Option<&str>,
+    ) -> Result<(), anyhow::Error>;
+}
+
+pub struct NoChallenge;
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for NoChallenge {
+    fn form_html(&self, _: &str) -> String {
+        String::new()
+    }
+
+    async fn verify(&self, _: &str, _: &str, _: Option<&str>) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+/// Hashcash-style puzzle: find a nonce such that `sha256("{form_token}:{nonce}")`
+/// starts with `difficulty` zero bits.
+///
+/// A solution is only good for one submission, as `FormTokens::spend` turns away reused tokens.
+pub struct ProofOfWork {
+    difficulty: u32,
+}
+
+impl ProofOfWork {
+    pub fn new(difficulty: u32) -> Self {
+        Self { difficulty }
+    }
+
+    fn leading_zero_bits(digest: &[u8]) -> u32 {
+        let mut bits = 0;
+        for byte in digest {
+            bits += byte.leading_zeros();
+            if *byte != 0 {
+                break;
+            }
+        }
+        bits
+    }
+}
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for ProofOfWork {
+    fn form_html(&self, csp_nonce: &str) -> String {
+        format!(
+            r#"<input hidden type="text" name="challenge_response" value=""/>
+    <script nonce="{csp_nonce}">
+      (async (form, difficulty) => {{
+        const encoder = new TextEncoder();
+        const token = form.elements["form_token"].value;
+        const zeroBits = (digest) => {{
+          let bits = 0;
+          for (const byte of digest) {{
+            bits += Math.clz32(byte) - 24;
+            if (byte !== 0) break;
+          }}
+          return bits;
+        }};
+        for (let nonce = 0; ; nonce++) {{
+          const input = encoder.encode(token + ":" + nonce);
+          const digest = new Uint8Array(await crypto.subtle.digest("SHA-256", input));
+          if (zeroBits(digest) >= difficulty) {{
+            form.elements["challenge_response"].value = nonce;
+            return;
+          }}
+        }}
+      }})(document.currentScript.closest("form"), {difficulty});
+    </script>"#,
+            difficulty = self.difficulty
+        )
+    }
+
+    async fn verify(
+        &self,
+        form_token: &str,
+        response: &str,
+        _: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        let digest = Sha256::digest(format!("{form_token}:{response}").as_bytes());
+        if Self::leading_zero_bits(&digest) < self.difficulty {
+            anyhow::bail!("The proof of work is insufficient.");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use sha2::{Digest, Sha256};
+
+    use super::{ChallengeVerifier, ProofOfWork};
+
+    fn solve(form_token: &str, difficulty: u32) -> String {
+        (0u64..)
+            .map(|nonce| nonce.to_string())
+            .find(|nonce| {
+                let digest = Sha256::digest(format!("{form_token}:{nonce}").as_bytes());
+                ProofOfWork::leading_zero_bits(&digest) >= difficulty
+            })
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_solved_puzzle_is_accepted() {
+        let response = solve("a-form-token", 8);
+        assert_ok!(
+            ProofOfWork::new(8)
+                .verify("a-form-token", &response, None)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn a_solution_for_another_token_is_rejected() {
+        let response = solve("a-form-token", 12);
+        assert_err!(
+            ProofOfWork::new(12)
+                .verify("another-form-token", &response, None)
+                .await
+        );
+    }
+
+    #[test]
+    fn the_script_carries_the_csp_nonce() {
+        let html = ProofOfWork::new(8).form_html("a-nonce");
+        assert!(html.contains(r#"<script nonce="a-nonce">"#));
+    }
+
+    #[test]
+    fn leading_zero_bits_span_bytes() {
+        assert_eq!(ProofOfWork::leading_zero_bits(&[0, 0b0001_0000, 0]), 11);
+        assert_eq!(ProofOfWork::leading_zero_bits(&[0xff]), 0);
+    }
+}
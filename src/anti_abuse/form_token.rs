@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, Rng};
+use sha2::Sha256;
+
+use crate::signing_keys::SigningKeys;
+
+/// Issues and checks the signed render timestamp embedded in the subscribe form.
+///
+/// Bots tend to post the form instantly or replay a token scraped long ago,
+/// so tokens are only accepted between `min_age` and `max_age` after issue, and only once.
+#[derive(Clone)]
+pub struct FormTokens {
+    signing_keys: SigningKeys,
+    min_age: Duration,
+    max_age: Duration,
+}
+
+impl FormTokens {
+    pub fn new(signing_keys: SigningKeys, min_age: Duration, max_age: Duration) -> Self {
+        Self {
+            signing_keys,
+            min_age,
+            max_age,
+        }
+    }
+
+    pub fn issue(&self) -> String {
+        self.issue_at(Utc::now().timestamp())
+    }
+
+    pub fn check(&self, token: &str) -> Result<(), anyhow::Error> {
+        let (issued_at, nonce, tag) = match token.split('.').collect::<Vec<_>>()[..] {
+            [issued_at, nonce, tag] => (issued_at, nonce, tag),
+            _ => anyhow::bail!("The form token is malformed."),
+        };
+        let tag = hex::decode(tag).context("The form token is malformed.")?;
+        self.signing_keys
+            .verify(&tag, |mac| update(mac, issued_at, nonce))
+            .context("The form token signature is invalid.")?;
+
+        let issued_at: i64 = issued_at.parse().context("The form token is malformed.")?;
+        let age = Utc::now().timestamp() - issued_at;
+        if age < self.min_age.as_secs() as i64 {
+            anyhow::bail!("The form was submitted too quickly after being rendered.");
+        }
+        if age > self.max_age.as_secs() as i64 {
+            anyhow::bail!("The form token has expired.");
+        }
+        Ok(())
+    }
+
+    /// Records `token` as used, returning `false` if it already was.
+    ///
+    /// Otherwise a challenge solved once could be replayed until the token expires. Only call it
+    /// once `check` and the challenge have passed, so a failed attempt doesn't burn the token.
+    pub async fn spend(&self, token: &str, redis: &redis::Client) -> Result<bool, anyhow::Error> {
+        let mut connection = redis
+            .get_async_connection()
+            .await
+            .context("Failed to connect to Redis.")?;
+        // kept until the token would have expired anyway
+        let stored: Option<String> = redis::cmd("SET")
+            .arg(format!("used_form_token:{token}"))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(self.max_age.as_secs().max(1))
+            .query_async(&mut connection)
+            .await
+            .context("Failed to store the form token.")?;
+        Ok(stored.is_some())
+    }
+
+    fn issue_at(&self, issued_at: i64) -> String {
+        let issued_at = issued_at.to_string();
+        // tells apart the forms rendered in the same second, as each can only be used once
+        let nonce = hex::encode(thread_rng().gen::<[u8; 8]>());
+        let mut mac = self.signing_keys.mac();
+        update(&mut mac, &issued_at, &nonce);
+        let tag = hex::encode(mac.finalize().into_bytes());
+        format!("{issued_at}.{nonce}.{tag}")
+    }
+}
+
+fn update(mac: &mut Hmac<Sha256>, issued_at: &str, nonce: &str) {
+    mac.update(b"subscribe-form:");
+    mac.update(issued_at.as_bytes());
+    mac.update(b".");
+    mac.update(nonce.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    use super::FormTokens;
+    use crate::signing_keys::SigningKeys;
+
+    fn form_tokens() -> FormTokens {
+        FormTokens::new(
+            SigningKeys::new(Secret::new("super-secret".into()), vec![]),
+            Duration::from_secs(3),
+            Duration::from_secs(3600),
+        )
+    }
+
+    #[test]
+    fn tokens_within_the_allowed_age_are_accepted() {
+        let token = form_tokens().issue_at(Utc::now().timestamp() - 10);
+        assert_ok!(form_tokens().check(&token));
+    }
+
+    #[test]
+    fn tokens_submitted_too_quickly_are_rejected() {
+        assert_err!(form_tokens().check(&form_tokens().issue()));
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let token = form_tokens().issue_at(Utc::now().timestamp() - 7200);
+        assert_err!(form_tokens().check(&token));
+    }
+
+    #[test]
+    fn tokens_with_a_tampered_timestamp_are_rejected() {
+        let token = form_tokens().issue_at(Utc::now().timestamp());
+        let (_, rest) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", Utc::now().timestamp() - 10, rest);
+        assert_err!(form_tokens().check(&forged));
+    }
+
+    #[test]
+    fn forms_rendered_in_the_same_second_get_different_tokens() {
+        let issued_at = Utc::now().timestamp() - 10;
+        let first = form_tokens().issue_at(issued_at);
+        let second = form_tokens().issue_at(issued_at);
+        assert_ne!(first, second);
+        assert_ok!(form_tokens().check(&first));
+        assert_ok!(form_tokens().check(&second));
+    }
+}
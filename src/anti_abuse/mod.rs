@@ -0,0 +1,7 @@
+mod challenge;
+mod form_token;
+mod rate_limit;
+
+pub use challenge::{ChallengeVerifier, NoChallenge, ProofOfWork};
+pub use form_token::FormTokens;
+pub use rate_limit::{check_subscription_rate, RateLimit};
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+
+#[derive(Debug, Clone, Copy)]
+pub enum RateLimit {
+    PerIp,
+    PerEmail,
+}
+
+impl std::fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateLimit::PerIp => write!(f, "per-IP"),
+            RateLimit::PerEmail => write!(f, "per-address"),
+        }
+    }
+}
+
+/// Records a subscription attempt and returns the limit it exceeds, if any.
+///
+/// Attempts are recorded even when rejected, so hammering the endpoint keeps the limit engaged.
+/// `email` should be the canonical form, so the variants of an address share a limit.
+#[tracing::instrument(name = "Checking subscription rate limits", skip(pool, ip, email))]
+pub async fn check_subscription_rate(
+    pool: &PgPool,
+    ip: &str,
+    email: &str,
+    window: Duration,
+    max_attempts_per_ip: i64,
+    max_attempts_per_email: i64,
+) -> Result<Option<RateLimit>, sqlx::Error> {
+    let window = window.as_secs_f64();
+
+    sqlx::query!(
+        r#"
+        DELETE FROM subscription_attempts
+        WHERE attempted_at < now() - make_interval(secs => $1)
+        "#,
+        window,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_attempts (ip, email, attempted_at)
+        VALUES ($1, $2, now())
+        "#,
+        ip,
+        email,
+    )
+    .execute(pool)
+    .await?;
+
+    let counts = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE ip = $1) as "ip_attempts!",
+            COUNT(*) FILTER (WHERE email = $2) as "email_attempts!"
+        FROM subscription_attempts
+        WHERE attempted_at >= now() - make_interval(secs => $3)
+        "#,
+        ip,
+        email,
+        window,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if counts.ip_attempts > max_attempts_per_ip {
+        Ok(Some(RateLimit::PerIp))
+    } else if counts.email_attempts > max_attempts_per_email {
+        Ok(Some(RateLimit::PerEmail))
+    } else {
+        Ok(None)
+    }
+}
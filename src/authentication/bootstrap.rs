@@ -0,0 +1,109 @@
+use anyhow::Context;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::password::compute_password_hash;
+use crate::telemetry::spawn_blocking_with_tracing;
+
+/// The account inserted by `20221128232109_seed_user.sql`, whose password is public.
+const SEEDED_ADMIN_ID: &str = "58adc858-88bd-4ec6-aecc-800367858fe9";
+const SEEDED_ADMIN_PASSWORD_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$WQFskHQ3dhvvIYpB5KVVlw$HBDCcv999PzA9Q3UJfn+SsGJWwFWR5IJkVZVwwg1nA8";
+
+pub struct BootstrapCredential {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// Makes sure there is an admin account whose password isn't public.
+///
+/// If `users` is empty an `admin` account is created; if the seeded account still has its
+/// published password, that password is replaced. Either way the returned one-off password
+/// must be changed on first login. Returns `None` when there was nothing to do.
+#[tracing::instrument(name = "Bootstrapping admin account", skip(pool))]
+pub async fn bootstrap_admin(pool: &PgPool) -> Result<Option<BootstrapCredential>, anyhow::Error> {
+    let seeded_admin_id = Uuid::parse_str(SEEDED_ADMIN_ID).unwrap();
+
+    // the usual case, checked without hashing or locking anything on every startup
+    let needed = sqlx::query_scalar!(
+        r#"
+        SELECT
+            EXISTS (SELECT 1 FROM users WHERE user_id = $1 AND password_hash = $2)
+            OR NOT EXISTS (SELECT 1 FROM users) AS "needed!"
+        "#,
+        seeded_admin_id,
+        SEEDED_ADMIN_PASSWORD_HASH,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to check whether an admin account is needed.")?;
+    if !needed {
+        return Ok(None);
+    }
+
+    let password = Secret::new(
+        thread_rng()
+            .sample_iter(&Alphanumeric)
+            .map(char::from)
+            .take(24)
+            .collect::<String>(),
+    );
+    let password_hash = {
+        let password = password.clone();
+        spawn_blocking_with_tracing(move || compute_password_hash(password))
+            .await?
+            .context("Failed to hash password.")?
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to open database transaction.")?;
+    // instances starting side by side must not both bootstrap, so the queries below check again
+    sqlx::query!("LOCK TABLE users IN EXCLUSIVE MODE")
+        .execute(&mut transaction)
+        .await
+        .context("Failed to lock the users table.")?;
+
+    let username = sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1, must_change_password = true, password_changed_at = now()
+        WHERE user_id = $2 AND password_hash = $3
+        RETURNING username
+        "#,
+        password_hash.expose_secret(),
+        seeded_admin_id,
+        SEEDED_ADMIN_PASSWORD_HASH,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to replace the seeded admin password.")?
+    .map(|r| r.username);
+
+    let username = match username {
+        Some(username) => Some(username),
+        None => sqlx::query!(
+            r#"
+            INSERT INTO users (user_id, username, password_hash, must_change_password)
+            SELECT $1, 'admin', $2, true
+            WHERE NOT EXISTS (SELECT 1 FROM users)
+            RETURNING username
+            "#,
+            Uuid::new_v4(),
+            password_hash.expose_secret(),
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to create the admin account.")?
+        .map(|r| r.username),
+    };
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to bootstrap the admin account.")?;
+
+    Ok(username.map(|username| BootstrapCredential { username, password }))
+}
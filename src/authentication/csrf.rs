@@ -0,0 +1,178 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::PayloadError,
+    http::header::{ORIGIN, REFERER},
+    web, FromRequest,
+};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use reqwest::Url;
+
+use crate::{
+    routes::{e403, e500},
+    session_state::TypedSession,
+};
+
+#[derive(serde::Deserialize)]
+struct CsrfForm {
+    csrf_token: String,
+}
+
+/// Rejects state-changing requests that did not come from one of our own forms.
+///
+/// Runs behind `reject_anonymous_users`, so there is always a session to check the token against.
+pub async fn reject_forged_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    if !req.method().is_safe() {
+        if let Err(e) = check_origin(&req) {
+            return Ok(forbidden(req, e));
+        }
+
+        let body = req.extract::<web::Bytes>().await?;
+        let session = {
+            let (http_request, payload) = req.parts_mut();
+            TypedSession::from_request(http_request, payload).await
+        }?;
+        let expected = session
+            .get_csrf_token()
+            .map_err(e500)?
+            .context("There is no CSRF token in the session.");
+        if let Err(e) = expected.and_then(|expected| check_csrf_token(&body, &expected)) {
+            return Ok(forbidden(req, e));
+        }
+
+        // hand the body we consumed back to the handler
+        req.set_payload(Payload::Stream {
+            payload: Box::pin(futures_util::stream::once(async move {
+                Ok::<_, PayloadError>(body)
+            })),
+        });
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// A response rather than an error, so outer middleware (e.g. security headers) still sees it.
+fn forbidden<B>(req: ServiceRequest, e: anyhow::Error) -> ServiceResponse<EitherBody<B>> {
+    tracing::warn!(error.message = %e, "Rejected a possibly forged request");
+    req.error_response(e403(e)).map_into_right_body()
+}
+
+/// Browsers name the page a request came from in `Origin`, or failing that `Referer`.
+///
+/// Either can be stripped by privacy settings, in which case we rely on the token alone.
+fn check_origin(req: &ServiceRequest) -> Result<(), anyhow::Error> {
+    let source = match req
+        .headers()
+        .get(ORIGIN)
+        .or_else(|| req.headers().get(REFERER))
+    {
+        Some(source) => source
+            .to_str()
+            .context("The request origin is malformed.")?,
+        None => return Ok(()),
+    };
+    // an opaque origin ("null") fails to parse, and is rejected with the rest
+    let url = Url::parse(source).context("The request origin is malformed.")?;
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => anyhow::bail!("The request origin has no host."),
+    };
+
+    if host != req.connection_info().host() {
+        anyhow::bail!("The request came from another site ({host}).");
+    }
+    Ok(())
+}
+
+fn check_csrf_token(body: &[u8], expected: &str) -> Result<(), anyhow::Error> {
+    let form: CsrfForm =
+        serde_urlencoded::from_bytes(body).context("The CSRF token is missing.")?;
+
+    // compare in constant time, so the token cannot be guessed a byte at a time
+    let submitted = form.csrf_token.as_bytes();
+    let difference = submitted
+        .iter()
+        .zip(expected.as_bytes())
+        .fold(0, |acc, (a, b)| acc | (a ^ b));
+    if submitted.len() != expected.len() || difference != 0 {
+        anyhow::bail!("The CSRF token is invalid.");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::header::{HOST, ORIGIN, REFERER},
+        test::TestRequest,
+    };
+    use claim::{assert_err, assert_ok};
+
+    use super::{check_csrf_token, check_origin};
+
+    fn request(headers: &[(&str, &str)]) -> actix_web::dev::ServiceRequest {
+        headers
+            .iter()
+            .fold(
+                TestRequest::post().insert_header((HOST, "newsletter.example:8000")),
+                |r, header| r.insert_header(*header),
+            )
+            .to_srv_request()
+    }
+
+    #[test]
+    fn requests_from_our_own_pages_are_accepted() {
+        assert_ok!(check_origin(&request(&[(
+            ORIGIN.as_str(),
+            "https://newsletter.example:8000"
+        )])));
+        assert_ok!(check_origin(&request(&[(
+            REFERER.as_str(),
+            "https://newsletter.example:8000/admin/password"
+        )])));
+    }
+
+    #[test]
+    fn requests_without_origin_or_referer_are_left_to_the_token() {
+        assert_ok!(check_origin(&request(&[])));
+    }
+
+    #[test]
+    fn requests_from_other_sites_are_rejected() {
+        assert_err!(check_origin(&request(&[(
+            ORIGIN.as_str(),
+            "https://evil.example"
+        )])));
+        assert_err!(check_origin(&request(&[(
+            REFERER.as_str(),
+            "https://newsletter.example.evil.example:8000/"
+        )])));
+        assert_err!(check_origin(&request(&[(ORIGIN.as_str(), "null")])));
+    }
+
+    #[test]
+    fn origin_takes_precedence_over_referer() {
+        assert_err!(check_origin(&request(&[
+            (ORIGIN.as_str(), "https://evil.example"),
+            (
+                REFERER.as_str(),
+                "https://newsletter.example:8000/admin/password"
+            ),
+        ])));
+    }
+
+    #[test]
+    fn only_the_exact_token_is_accepted() {
+        assert_ok!(check_csrf_token(b"title=Hi&csrf_token=abc123", "abc123"));
+        assert_err!(check_csrf_token(b"title=Hi&csrf_token=abc12", "abc123"));
+        assert_err!(check_csrf_token(b"title=Hi&csrf_token=abc1234", "abc123"));
+        assert_err!(check_csrf_token(b"title=Hi", "abc123"));
+    }
+}
@@ -1,16 +1,22 @@
 use std::ops::Deref;
 
 use actix_web::{
-    body::MessageBody,
+    body::{EitherBody, MessageBody},
     dev::{ServiceRequest, ServiceResponse},
-    error::InternalError,
-    HttpMessage,
+    web, HttpMessage,
 };
 
+use actix_web_flash_messages::FlashMessage;
 use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
 use uuid::Uuid;
 
+use super::{touch_session, SessionClient, SessionStatus};
 use crate::{
+    client_ip::TrustedProxies,
+    configuration::{AuthenticationSettings, SessionSettings},
     routes::{e500, see_other},
     session_state::TypedSession,
 };
@@ -33,24 +39,137 @@ impl Deref for UserId {
     }
 }
 
+/// Still reachable while a password change is pending, so the user can make it (or give up).
+const PASSWORD_CHANGE_EXEMPT_PATHS: &[&str] = &["/admin/password", "/admin/logout"];
+
 pub async fn reject_anonymous_users(
     mut req: ServiceRequest,
     next: Next<impl MessageBody>,
-) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
     let session = {
         let (http_request, payload) = req.parts_mut();
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match session.get_user_id().map_err(e500)? {
-        Some(user_id) => {
-            req.extensions_mut().insert(UserId(user_id));
-            next.call(req).await
+    let settings = req
+        .app_data::<web::Data<SessionSettings>>()
+        .cloned()
+        .context("The session settings are not registered.")
+        .map_err(e500)?;
+
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        // the cookie outlived the idle timeout of its state in Redis
+        None if req.cookie(&settings.cookie_name).is_some() => {
+            session.logout();
+            return Ok(redirect_with_flash(req, "/login", SESSION_EXPIRED));
+        }
+        None => return Ok(login_required(req)),
+    };
+    match session_status(&req, &session, user_id, &settings)
+        .await
+        .map_err(e500)?
+    {
+        SessionStatus::Live => {}
+        SessionStatus::Expired => {
+            session.logout();
+            return Ok(redirect_with_flash(req, "/login", SESSION_EXPIRED));
         }
-        None => {
-            let response = see_other("/login");
-            let e = anyhow::anyhow!("The user has not logged in.");
-            Err(InternalError::from_response(e, response).into())
+        SessionStatus::Revoked => {
+            // drop what is left of it in the store
+            session.logout();
+            return Ok(login_required(req));
         }
     }
+
+    if !PASSWORD_CHANGE_EXEMPT_PATHS.contains(&req.path())
+        && password_change_required(&req, user_id)
+            .await
+            .map_err(e500)?
+    {
+        return Ok(redirect_with_flash(
+            req,
+            "/admin/password",
+            "You must choose a new password before continuing.",
+        ));
+    }
+    req.extensions_mut().insert(UserId(user_id));
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+const SESSION_EXPIRED: &str = "Your session has expired. Please log in again.";
+
+/// A response rather than an error, otherwise outer middleware (e.g. security headers) never sees it.
+fn login_required<B>(req: ServiceRequest) -> ServiceResponse<EitherBody<B>> {
+    req.into_response(see_other("/login")).map_into_right_body()
+}
+
+/// Likewise, and the flash message would be lost.
+fn redirect_with_flash<B>(
+    req: ServiceRequest,
+    location: &str,
+    message: &str,
+) -> ServiceResponse<EitherBody<B>> {
+    FlashMessage::info(message).send();
+    req.into_response(see_other(location)).map_into_right_body()
+}
+
+/// Looks the session up in `user_sessions`, recording the visit if it is still live.
+async fn session_status(
+    req: &ServiceRequest,
+    session: &TypedSession,
+    user_id: Uuid,
+    settings: &SessionSettings,
+) -> Result<SessionStatus, anyhow::Error> {
+    let session_id = match session.get_session_id()? {
+        Some(session_id) => session_id,
+        None => return Ok(SessionStatus::Revoked),
+    };
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .context("The database pool is not registered.")?;
+    let trusted_proxies = req
+        .app_data::<web::Data<TrustedProxies>>()
+        .context("The trusted proxies are not registered.")?;
+
+    touch_session(
+        session_id,
+        user_id,
+        &SessionClient::from_request(req.request(), trusted_proxies),
+        settings,
+        pool,
+    )
+    .await
+}
+
+/// Whether the password was flagged for a change (e.g. after a reset) or is too old.
+async fn password_change_required(
+    req: &ServiceRequest,
+    user_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .context("The database pool is not registered.")?;
+    let settings = req
+        .app_data::<web::Data<AuthenticationSettings>>()
+        .context("The authentication settings are not registered.")?;
+
+    let r = sqlx::query!(
+        r#"
+        SELECT must_change_password, password_changed_at
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to retrieve the password status of the user.")?;
+
+    let expired = settings
+        .max_password_age()
+        .is_some_and(|max_age| r.password_changed_at + max_age < Utc::now());
+    Ok(r.must_change_password || expired)
 }
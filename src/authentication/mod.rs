@@ -1,5 +1,19 @@
+mod bootstrap;
+pub use bootstrap::{bootstrap_admin, BootstrapCredential};
+
 mod password;
-pub use password::{change_password, validate_credential, AuthError, Credential};
+pub use password::{
+    change_password, create_user, reset_password, validate_credential, AuthError, Credential,
+};
+
+mod csrf;
+pub use csrf::reject_forged_requests;
 
 mod middleware;
 pub use middleware::{reject_anonymous_users, UserId};
+
+mod sessions;
+pub use sessions::{
+    list_sessions, record_session, revoke_session, revoke_sessions, touch_session, SessionClient,
+    SessionRecord, SessionStatus,
+};
@@ -7,7 +7,8 @@ use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::telemetry::spawn_blocking_with_tracing;
+use super::sessions::revoke_sessions;
+use crate::{metrics::time_password_verification, telemetry::spawn_blocking_with_tracing};
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthError {
@@ -46,7 +47,9 @@ pub async fn validate_credential(
         expected_password_hash = stored_password_hash;
     }
 
+    let timer = time_password_verification();
     spawn_blocking_with_tracing(move || {
+        let _timer = timer;
         verify_password_hash(expected_password_hash, credential.password)
     })
     .await
@@ -68,7 +71,7 @@ pub async fn change_password(
     sqlx::query!(
         r#"
         UPDATE users
-        SET password_hash = $1
+        SET password_hash = $1, must_change_password = false, password_changed_at = now()
         WHERE user_id = $2
         "#,
         password_hash.expose_secret(),
@@ -81,7 +84,68 @@ pub async fn change_password(
     Ok(())
 }
 
-fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+/// Sets a password chosen by someone else, e.g. an operator, which the user must change on next login.
+#[tracing::instrument(name = "Resetting password", skip(password, pool))]
+pub async fn reset_password(
+    username: &str,
+    password: Secret<String>,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password.")?;
+
+    let user_id = sqlx::query_scalar!(
+        r#"
+        UPDATE users
+        SET password_hash = $1, must_change_password = true, password_changed_at = now()
+        WHERE username = $2
+        RETURNING user_id
+        "#,
+        password_hash.expose_secret(),
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to reset password in database.")?
+    .with_context(|| format!("There is no user called {username}."))?;
+
+    // whoever knew the old password may still be logged in
+    revoke_sessions(user_id, None, pool).await
+}
+
+#[tracing::instrument(name = "Creating user", skip(password, pool))]
+pub async fn create_user(
+    username: &str,
+    password: Secret<String>,
+    must_change_password: bool,
+    pool: &PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password.")?;
+
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, must_change_password)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        must_change_password,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store new user in database.")?;
+
+    Ok(user_id)
+}
+
+pub(super) fn compute_password_hash(
+    password: Secret<String>,
+) -> Result<Secret<String>, anyhow::Error> {
     let salt = SaltString::generate(rand::thread_rng());
     let password_hash = make_argon()
         .hash_password(password.expose_secret().as_bytes(), &salt)
@@ -0,0 +1,191 @@
+use actix_web::{http::header::USER_AGENT, HttpRequest};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{client_ip::TrustedProxies, configuration::SessionSettings};
+
+/// Where a session is being used from.
+pub struct SessionClient {
+    pub ip: String,
+    pub user_agent: String,
+}
+
+impl SessionClient {
+    pub fn from_request(request: &HttpRequest, trusted_proxies: &TrustedProxies) -> Self {
+        let ip = trusted_proxies.client_ip(request);
+        let user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+        Self { ip, user_agent }
+    }
+}
+
+pub enum SessionStatus {
+    Live,
+    /// Past the absolute timeout.
+    Expired,
+    /// Ended by the user, e.g. from another device or by changing their password.
+    Revoked,
+}
+
+pub struct SessionRecord {
+    pub session_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub ip: String,
+    pub user_agent: String,
+}
+
+/// Registers a new login, returning the id to keep in the session state.
+#[tracing::instrument(name = "Recording session", skip(client, settings, pool))]
+pub async fn record_session(
+    user_id: Uuid,
+    client: &SessionClient,
+    settings: &SessionSettings,
+    pool: &PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    // the session state has expired in Redis by now, the rows are just clutter
+    sqlx::query!(
+        r#"
+        DELETE FROM user_sessions
+        WHERE user_id = $1
+            AND (last_seen_at < now() - make_interval(secs => $2)
+                OR created_at < now() - make_interval(secs => $3))
+        "#,
+        user_id,
+        settings.idle_timeout().as_secs_f64(),
+        settings.absolute_timeout().as_secs_f64(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to remove stale sessions.")?;
+
+    let session_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sessions (session_id, user_id, created_at, last_seen_at, ip, user_agent)
+        VALUES ($1, $2, now(), now(), $3, $4)
+        "#,
+        session_id,
+        user_id,
+        client.ip,
+        client.user_agent,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record session.")?;
+
+    Ok(session_id)
+}
+
+/// Marks a session as seen, unless it has expired (which ends it) or been revoked.
+pub async fn touch_session(
+    session_id: Uuid,
+    user_id: Uuid,
+    client: &SessionClient,
+    settings: &SessionSettings,
+    pool: &PgPool,
+) -> Result<SessionStatus, anyhow::Error> {
+    let created_at = sqlx::query_scalar!(
+        r#"
+        UPDATE user_sessions
+        SET last_seen_at = now(), ip = $3, user_agent = $4
+        WHERE session_id = $1 AND user_id = $2
+        RETURNING created_at
+        "#,
+        session_id,
+        user_id,
+        client.ip,
+        client.user_agent,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update session.")?;
+
+    match created_at {
+        None => Ok(SessionStatus::Revoked),
+        Some(created_at)
+            if (Utc::now() - created_at)
+                .to_std()
+                .is_ok_and(|age| age > settings.absolute_timeout()) =>
+        {
+            revoke_session(user_id, session_id, pool).await?;
+            Ok(SessionStatus::Expired)
+        }
+        Some(_) => Ok(SessionStatus::Live),
+    }
+}
+
+#[tracing::instrument(name = "Listing sessions", skip(settings, pool))]
+pub async fn list_sessions(
+    user_id: Uuid,
+    settings: &SessionSettings,
+    pool: &PgPool,
+) -> Result<Vec<SessionRecord>, anyhow::Error> {
+    sqlx::query_as!(
+        SessionRecord,
+        r#"
+        SELECT session_id, created_at, last_seen_at, ip, user_agent
+        FROM user_sessions
+        WHERE user_id = $1
+            AND last_seen_at >= now() - make_interval(secs => $2)
+            AND created_at >= now() - make_interval(secs => $3)
+        ORDER BY last_seen_at DESC
+        "#,
+        user_id,
+        settings.idle_timeout().as_secs_f64(),
+        settings.absolute_timeout().as_secs_f64(),
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve sessions.")
+}
+
+/// Ends one of the user's sessions, returning `false` if there was no such session.
+#[tracing::instrument(name = "Revoking session", skip(pool))]
+pub async fn revoke_session(
+    user_id: Uuid,
+    session_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM user_sessions
+        WHERE user_id = $1 AND session_id = $2
+        "#,
+        user_id,
+        session_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke session.")?;
+
+    Ok(deleted.rows_affected() == 1)
+}
+
+/// Ends all of the user's sessions apart from `keep`, if given.
+#[tracing::instrument(name = "Revoking sessions", skip(pool))]
+pub async fn revoke_sessions(
+    user_id: Uuid,
+    keep: Option<Uuid>,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM user_sessions
+        WHERE user_id = $1 AND session_id IS DISTINCT FROM $2
+        "#,
+        user_id,
+        keep,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke sessions.")?;
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use std::net::IpAddr;
+
+use actix_web::HttpRequest;
+
+/// The load balancers and reverse proxies in front of us, whose `X-Forwarded-For` we believe.
+///
+/// Anyone else can put whatever they like in that header, so it is ignored unless the
+/// connection comes from one of them.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(Vec<IpAddr>);
+
+impl TrustedProxies {
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        Self(proxies)
+    }
+
+    fn trusts(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+
+    /// The address the request came from, or `"unknown"` in tests that don't connect.
+    pub fn client_ip(&self, request: &HttpRequest) -> String {
+        let peer = match request.peer_addr() {
+            Some(peer) => peer.ip(),
+            None => return "unknown".into(),
+        };
+        let forwarded_for = request
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .map(str::trim);
+        self.client_ip_behind(peer, forwarded_for).to_string()
+    }
+
+    /// Walks back from `peer` through the addresses our proxies appended, until one we don't
+    /// trust: the entries before it could have been made up by the client.
+    fn client_ip_behind<'a>(
+        &self,
+        peer: IpAddr,
+        forwarded_for: impl DoubleEndedIterator<Item = &'a str>,
+    ) -> IpAddr {
+        let mut client = peer;
+        for hop in forwarded_for.rev() {
+            if !self.trusts(&client) {
+                break;
+            }
+            match hop.parse() {
+                Ok(hop) => client = hop,
+                Err(_) => break,
+            }
+        }
+        client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::TrustedProxies;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let proxies = TrustedProxies::default();
+        let client = proxies.client_ip_behind(ip("203.0.113.7"), ["198.51.100.1"].into_iter());
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn the_address_our_proxy_saw_is_used() {
+        let proxies = TrustedProxies::new(vec![ip("10.0.0.2")]);
+        let client = proxies.client_ip_behind(ip("10.0.0.2"), ["203.0.113.7"].into_iter());
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn addresses_made_up_by_the_client_are_skipped() {
+        let proxies = TrustedProxies::new(vec![ip("10.0.0.2"), ip("10.0.0.3")]);
+        // the client sent `198.51.100.1`, our two proxies appended the rest
+        let client = proxies.client_ip_behind(
+            ip("10.0.0.3"),
+            ["198.51.100.1", "203.0.113.7", "10.0.0.2"].into_iter(),
+        );
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn garbage_stops_the_walk() {
+        let proxies = TrustedProxies::new(vec![ip("10.0.0.2")]);
+        let client = proxies.client_ip_behind(ip("10.0.0.2"), ["not-an-ip"].into_iter());
+        assert_eq!(client, ip("10.0.0.2"));
+    }
+}
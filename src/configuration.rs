@@ -1,14 +1,44 @@
-use std::time::Duration;
+use std::{net::IpAddr, sync::Arc, time::Duration};
 
+use actix_session::{
+    config::{BrowserSession, TtlExtensionPolicy},
+    storage::RedisSessionStore,
+    SessionMiddleware,
+};
+use actix_web::cookie::{Key, SameSite};
+use anyhow::Context;
+use opentelemetry::sdk::trace::Tracer;
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::{
     postgres::{PgConnectOptions, PgSslMode},
     ConnectOptions,
 };
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::{
+    anti_abuse::{ChallengeVerifier, FormTokens, NoChallenge, ProofOfWork},
+    client_ip::TrustedProxies,
+    domain::{DnsMxResolver, EmailValidator, MxResolver, SubscriberEmail},
+    email_client::EmailClient,
+    issue_deliver_worker::SendRateLimiter,
+    redaction::Redaction,
+    security_headers::SecurityHeaders,
+    signing_keys::SigningKeys,
+    telemetry::otlp_tracer,
+};
+
+mod secrets;
+mod sources;
+mod validation;
+
+pub use secrets::{
+    FileSecretProvider, SecretProvider, StubSecretProvider, SECRET_KEYS, SECRET_LIST_KEYS,
+};
+pub use sources::{ConfigSources, Environment};
+pub use validation::{ConfigProblem, InvalidConfiguration};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
@@ -16,6 +46,19 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub session: SessionSettings,
+    pub security_headers: SecurityHeadersSettings,
+    pub tracking: TrackingSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub email_validation: EmailValidationSettings,
+    pub worker: WorkerSettings,
+    #[serde(default)]
+    pub authentication: AuthenticationSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    /// Where each of the above came from, for the startup logs.
+    #[serde(skip)]
+    pub sources: ConfigSources,
 }
 
 #[derive(Deserialize, Clone)]
@@ -25,12 +68,33 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// Secrets `hmac_secret` replaced, still accepted for what was signed with them.
+    #[serde(default)]
+    pub previous_hmac_secrets: Vec<Secret<String>>,
+    /// How long in-flight requests and deliveries get to finish once we're asked to stop.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub shutdown_timeout_seconds: u64,
+    /// The load balancers whose `X-Forwarded-For` is believed, see `TrustedProxies`.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 impl ApplicationSettings {
     pub fn get_address(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_seconds)
+    }
+
+    pub fn trusted_proxies(&self) -> TrustedProxies {
+        TrustedProxies::new(self.trusted_proxies.clone())
+    }
+
+    pub fn signing_keys(&self) -> SigningKeys {
+        SigningKeys::new(self.hmac_secret.clone(), self.previous_hmac_secrets.clone())
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -42,6 +106,8 @@ pub struct DatabaseSettings {
     pub host: String,
     pub database_name: String,
     pub require_ssl: bool,
+    /// Apply pending migrations in `Application::build`, rather than via `zero2prod migrate`.
+    pub migrate_on_startup: bool,
 }
 
 impl DatabaseSettings {
@@ -77,16 +143,19 @@ pub struct EmailClientSettings {
 }
 
 impl EmailClientSettings {
-    pub fn client(self) -> EmailClient {
-        let sender_email = self.sender().expect("Invalid sender email address.");
+    pub fn client(self) -> Result<EmailClient, anyhow::Error> {
+        let sender_email = self
+            .sender()
+            .map_err(anyhow::Error::msg)
+            .context("Invalid email_client.sender_email.")?;
 
         let timeout = self.timeout();
-        EmailClient::new(
+        Ok(EmailClient::new(
             self.base_url,
             sender_email,
             self.authorization_token,
             timeout,
-        )
+        ))
     }
 
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
@@ -98,59 +167,298 @@ impl EmailClientSettings {
     }
 }
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine the current directory.");
-    let configuration_directory = base_path.join("configuration");
+#[derive(Deserialize, Clone)]
+pub struct SessionSettings {
+    pub cookie_name: String,
+    pub cookie_secure: bool,
+    pub cookie_same_site: CookieSameSite,
+    /// Scopes the cookie to the serving host only if missing.
+    pub cookie_domain: Option<String>,
+    /// A session without activity for this long is dropped from Redis.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idle_timeout_seconds: u64,
+    /// A session this old is ended however active it has been.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub absolute_timeout_seconds: u64,
+    /// Whether every request counts as activity, or only changes to the session state.
+    pub extend_on_every_request: bool,
+}
 
-    let environment: Environment = std::env::var("APP_ENVIRONMENT")
-        .unwrap_or_else(|_| "local".into())
-        .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT.");
-    let environment_filename = format!("{}.yaml", environment.as_str());
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
 
-    let configuration = config::Config::builder()
-        .add_source(config::File::from(
-            configuration_directory.join("base.yaml"),
-        ))
-        .add_source(config::File::from(
-            configuration_directory.join(environment_filename),
-        ))
-        .add_source(
-            config::Environment::with_prefix("APP")
-                .prefix_separator("_")
-                .separator("__"),
+impl SessionSettings {
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_seconds)
+    }
+
+    pub fn absolute_timeout(&self) -> Duration {
+        Duration::from_secs(self.absolute_timeout_seconds)
+    }
+
+    pub fn middleware(
+        &self,
+        store: RedisSessionStore,
+        key: Key,
+    ) -> SessionMiddleware<RedisSessionStore> {
+        let same_site = match self.cookie_same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        };
+        let ttl_extension_policy = if self.extend_on_every_request {
+            TtlExtensionPolicy::OnEveryRequest
+        } else {
+            TtlExtensionPolicy::OnStateChanges
+        };
+
+        SessionMiddleware::builder(store, key)
+            .cookie_name(self.cookie_name.clone())
+            .cookie_secure(self.cookie_secure)
+            .cookie_same_site(same_site)
+            .cookie_domain(self.cookie_domain.clone())
+            .session_lifecycle(
+                BrowserSession::default()
+                    .state_ttl(actix_web::cookie::time::Duration::seconds(
+                        self.idle_timeout_seconds as i64,
+                    ))
+                    .state_ttl_extension_policy(ttl_extension_policy),
+            )
+            .build()
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SecurityHeadersSettings {
+    /// Every `{nonce}` is replaced with a fresh nonce for each response.
+    pub content_security_policy: String,
+    pub frame_options: String,
+    pub referrer_policy: String,
+    /// Only sent when `application.base_url` is https.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub hsts_max_age_seconds: u64,
+}
+
+impl SecurityHeadersSettings {
+    pub fn headers(&self, base_url: &str) -> Result<SecurityHeaders, anyhow::Error> {
+        // over plain http the header would be ignored, or pin a dev box to https if it weren't
+        let hsts_max_age = base_url
+            .starts_with("https://")
+            .then(|| Duration::from_secs(self.hsts_max_age_seconds));
+        SecurityHeaders::new(
+            self.content_security_policy.clone(),
+            &self.frame_options,
+            &self.referrer_policy,
+            hsts_max_age,
         )
-        .build()?;
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TrackingSettings {
+    pub enabled: bool,
+}
 
-    configuration.try_deserialize::<Settings>()
+#[derive(Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_ip: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_email: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub rate_limit_window_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_form_fill_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_form_age_seconds: u64,
+    pub challenge: ChallengeSettings,
 }
 
-enum Environment {
-    Local,
-    Production,
+#[derive(Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChallengeSettings {
+    None,
+    ProofOfWork {
+        #[serde(deserialize_with = "deserialize_number_from_string")]
+        difficulty: u32,
+    },
 }
 
-impl Environment {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Environment::Local => "local",
-            Environment::Production => "production",
+impl SubscriptionSettings {
+    pub fn form_tokens(&self, signing_keys: SigningKeys) -> FormTokens {
+        FormTokens::new(
+            signing_keys,
+            Duration::from_secs(self.min_form_fill_seconds),
+            Duration::from_secs(self.max_form_age_seconds),
+        )
+    }
+
+    pub fn challenge_verifier(&self) -> Arc<dyn ChallengeVerifier> {
+        match self.challenge {
+            ChallengeSettings::None => Arc::new(NoChallenge),
+            ChallengeSettings::ProofOfWork { difficulty } => Arc::new(ProofOfWork::new(difficulty)),
         }
     }
+
+    pub fn rate_limit_window(&self) -> Duration {
+        Duration::from_secs(self.rate_limit_window_seconds)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct EmailValidationSettings {
+    /// Dedupes Gmail addresses ignoring dots and `+tags`. Mail still goes to the address as typed.
+    pub canonicalize_gmail: bool,
+    pub check_mx: bool,
+    /// Domains rejected on top of the bundled disposable list, one per line.
+    pub disposable_domains_path: Option<String>,
+}
+
+impl EmailValidationSettings {
+    pub fn validator(&self) -> Result<EmailValidator, anyhow::Error> {
+        let extra_disposable_domains = match &self.disposable_domains_path {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read disposable domains from {path}."))?,
+            None => String::new(),
+        };
+        let mx_resolver: Option<Arc<dyn MxResolver>> = if self.check_mx {
+            Some(Arc::new(DnsMxResolver::from_system_conf()?))
+        } else {
+            None
+        };
+
+        Ok(EmailValidator::new(
+            self.canonicalize_gmail,
+            &extra_disposable_domains,
+            mx_resolver,
+        ))
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WorkerSettings {
+    /// Number of delivery loops running in parallel.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub concurrency: usize,
+    /// Cap on emails sent per second across all loops, unlimited if missing.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub max_emails_per_second: Option<u32>,
+    /// How often an idle worker checks the queue in case a notification was missed.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idle_poll_seconds: u64,
+    /// Where `zero2prod worker` serves `/metrics`, on `application.host`. Not served if missing.
+    /// Unused when the worker runs alongside the API, which serves the metrics of both.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub metrics_port: Option<u16>,
 }
 
-impl TryFrom<String> for Environment {
-    type Error = String;
+impl WorkerSettings {
+    pub fn send_rate_limiter(&self) -> Option<SendRateLimiter> {
+        self.max_emails_per_second.map(SendRateLimiter::per_second)
+    }
+
+    pub fn idle_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.idle_poll_seconds)
+    }
+}
 
-    fn try_from(s: String) -> Result<Self, Self::Error> {
-        match s.to_lowercase().as_str() {
-            "local" => Ok(Self::Local),
-            "production" => Ok(Self::Production),
-            _ => Err(format!(
-                "Unsupported environment \"{}\" \
-                Valid values: 'local', 'production'",
-                s
-            )),
+#[derive(Deserialize, Clone, Default)]
+pub struct AuthenticationSettings {
+    /// Passwords older than this must be changed before the admin area can be used again.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub max_password_age_days: Option<i64>,
+}
+
+impl AuthenticationSettings {
+    pub fn max_password_age(&self) -> Option<chrono::Duration> {
+        self.max_password_age_days.map(chrono::Duration::days)
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct TelemetrySettings {
+    /// Where to send spans over OTLP/gRPC, e.g. `http://localhost:4317`. Not exported if missing.
+    pub otlp_endpoint: Option<String>,
+    /// How personal data is written to the logs.
+    #[serde(default)]
+    pub redaction: Redaction,
+}
+
+impl TelemetrySettings {
+    pub fn tracer(&self, service_name: &str) -> Result<Option<Tracer>, anyhow::Error> {
+        self.otlp_endpoint
+            .as_deref()
+            .map(|endpoint| otlp_tracer(service_name.into(), endpoint, self.redaction))
+            .transpose()
+            .context("Failed to set up the OTLP exporter.")
+    }
+}
+
+/// Secrets are read from the files named by `<key>_file` settings, where there are any.
+pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+    let (configuration, sources) = load_sources()?;
+    let secrets = FileSecretProvider::from_config(&configuration);
+    with_secrets(configuration, sources, &secrets)
+}
+
+/// Like `get_configuration`, with secrets from `secrets` rather than files.
+pub fn get_configuration_with_secrets(
+    secrets: &dyn SecretProvider,
+) -> Result<Settings, config::ConfigError> {
+    let (configuration, sources) = load_sources()?;
+    with_secrets(configuration, sources, secrets)
+}
+
+fn load_sources() -> Result<(config::Config, ConfigSources), config::ConfigError> {
+    let base_path = std::env::current_dir().expect("Failed to determine the current directory.");
+    let configuration_directory = base_path.join("configuration");
+
+    let environment = match std::env::var("APP_ENVIRONMENT") {
+        Ok(environment) => {
+            Environment::try_from(environment).map_err(config::ConfigError::Message)?
+        }
+        Err(_) => Environment::default(),
+    };
+    sources::load(&configuration_directory, &environment)
+}
+
+/// Secrets from `secrets` take precedence over the files and environment variables.
+fn with_secrets(
+    configuration: config::Config,
+    mut sources: ConfigSources,
+    secrets: &dyn SecretProvider,
+) -> Result<Settings, config::ConfigError> {
+    let mut builder = config::Config::builder().add_source(configuration);
+    for key in SECRET_KEYS {
+        let secret = secrets
+            .secret(key)
+            .map_err(|e| config::ConfigError::Message(format!("{e:#}")))?;
+        if let Some(secret) = secret {
+            builder = builder.set_override(key, secret.expose_secret().as_str())?;
+            sources.add(key, "secret provider");
+        }
+    }
+    for key in SECRET_LIST_KEYS {
+        let secrets = secrets
+            .secret(key)
+            .map_err(|e| config::ConfigError::Message(format!("{e:#}")))?;
+        if let Some(secrets) = secrets {
+            let secrets: Vec<_> = secrets
+                .expose_secret()
+                .lines()
+                .filter(|secret| !secret.is_empty())
+                .map(String::from)
+                .collect();
+            builder = builder.set_override(key, secrets)?;
+            sources.add(key, "secret provider");
         }
     }
+    let mut settings = builder.build()?.try_deserialize::<Settings>()?;
+    settings.sources = sources;
+    Ok(settings)
 }
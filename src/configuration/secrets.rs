@@ -0,0 +1,187 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Context;
+use secrecy::Secret;
+
+/// The settings that hold secrets, by their key in the configuration.
+pub const SECRET_KEYS: [&str; 4] = [
+    "application.hmac_secret",
+    "database.password",
+    "email_client.authorization_token",
+    "redis_uri",
+];
+
+/// The settings that hold lists of secrets, one per line when read from a provider.
+pub const SECRET_LIST_KEYS: [&str; 1] = ["application.previous_hmac_secrets"];
+
+/// Somewhere secrets live other than the configuration files and `APP_*` variables.
+///
+/// Values are looked up afresh on every call, so a rotated secret is picked up the next time
+/// the configuration is loaded.
+pub trait SecretProvider {
+    /// The current value of the secret at `key`, e.g. `database.password`, if there is one.
+    fn secret(&self, key: &str) -> Result<Option<Secret<String>>, anyhow::Error>;
+}
+
+/// Reads secrets from files, e.g. Docker or Kubernetes secret mounts.
+pub struct FileSecretProvider {
+    paths: HashMap<String, PathBuf>,
+}
+
+impl FileSecretProvider {
+    pub fn new(paths: HashMap<String, PathBuf>) -> Self {
+        Self { paths }
+    }
+
+    /// Uses the file named by `<key>_file` for any secret that has one, e.g. `database.password_file`
+    /// in YAML or `APP_DATABASE__PASSWORD_FILE` in the environment.
+    pub fn from_config(configuration: &config::Config) -> Self {
+        let paths = SECRET_KEYS
+            .iter()
+            .chain(&SECRET_LIST_KEYS)
+            .filter_map(|key| {
+                let path = configuration.get_string(&format!("{key}_file")).ok()?;
+                Some((key.to_string(), PathBuf::from(path)))
+            })
+            .collect();
+        Self::new(paths)
+    }
+}
+
+impl SecretProvider for FileSecretProvider {
+    fn secret(&self, key: &str) -> Result<Option<Secret<String>>, anyhow::Error> {
+        let path = match self.paths.get(key) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let secret = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {key} from {}.", path.display()))?;
+        // editors and `echo` leave a trailing newline that isn't part of the secret
+        Ok(Some(Secret::new(
+            secret.trim_end_matches(['\r', '\n']).to_string(),
+        )))
+    }
+}
+
+/// Hands out fixed secrets, for local development and tests.
+#[derive(Default)]
+pub struct StubSecretProvider {
+    secrets: HashMap<String, String>,
+}
+
+impl StubSecretProvider {
+    pub fn new<'a>(secrets: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        Self {
+            secrets: secrets
+                .into_iter()
+                .map(|(key, secret)| (key.to_string(), secret.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl SecretProvider for StubSecretProvider {
+    fn secret(&self, key: &str) -> Result<Option<Secret<String>>, anyhow::Error> {
+        Ok(self.secrets.get(key).cloned().map(Secret::new))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use claim::{assert_err, assert_none};
+    use config::{Config, File, FileFormat};
+    use secrecy::ExposeSecret;
+    use uuid::Uuid;
+
+    use super::{FileSecretProvider, SecretProvider, StubSecretProvider};
+
+    fn secret_file(contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn secrets_are_read_from_their_files_without_the_trailing_newline() {
+        let path = secret_file("hunter2\n");
+        let provider = FileSecretProvider::new(HashMap::from([(
+            "database.password".to_string(),
+            path.clone(),
+        )]));
+
+        let secret = provider.secret("database.password").unwrap().unwrap();
+        assert_eq!(secret.expose_secret(), "hunter2");
+        assert_none!(provider.secret("redis_uri").unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn a_rotated_secret_is_read_again() {
+        let path = secret_file("old");
+        let provider =
+            FileSecretProvider::new(HashMap::from([("redis_uri".to_string(), path.clone())]));
+        assert_eq!(
+            provider
+                .secret("redis_uri")
+                .unwrap()
+                .unwrap()
+                .expose_secret(),
+            "old"
+        );
+
+        std::fs::write(&path, "new").unwrap();
+        assert_eq!(
+            provider
+                .secret("redis_uri")
+                .unwrap()
+                .unwrap()
+                .expose_secret(),
+            "new"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_files_are_reported_with_their_key() {
+        let provider = FileSecretProvider::new(HashMap::from([(
+            "database.password".to_string(),
+            std::env::temp_dir().join(Uuid::new_v4().to_string()),
+        )]));
+
+        let e = assert_err!(provider.secret("database.password"));
+        assert!(e.to_string().contains("database.password"));
+    }
+
+    #[test]
+    fn files_are_picked_up_from_file_keys() {
+        let path = secret_file("hunter2");
+        let configuration = Config::builder()
+            .add_source(File::from_str(
+                &format!("database:\n  password_file: \"{}\"", path.display()),
+                FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap();
+
+        let provider = FileSecretProvider::from_config(&configuration);
+        let secret = provider.secret("database.password").unwrap().unwrap();
+        assert_eq!(secret.expose_secret(), "hunter2");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn the_stub_only_knows_what_it_was_given() {
+        let provider = StubSecretProvider::new([("application.hmac_secret", "not-so-secret")]);
+        assert_eq!(
+            provider
+                .secret("application.hmac_secret")
+                .unwrap()
+                .unwrap()
+                .expose_secret(),
+            "not-so-secret"
+        );
+        assert_none!(provider.secret("database.password").unwrap());
+    }
+}
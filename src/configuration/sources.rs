@@ -0,0 +1,203 @@
+use std::{collections::BTreeMap, fmt::Display, path::Path};
+
+use config::{ConfigError, Source};
+
+/// Which configuration files are loaded, as set by `APP_ENVIRONMENT`.
+///
+/// Any name with a `configuration/<name>.yaml` file will do, e.g. `staging` or `test`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Environment(String);
+
+impl Environment {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self("local".into())
+    }
+}
+
+impl TryFrom<String> for Environment {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let name = s.to_lowercase();
+        // it ends up in a file name, so nothing that could leave the configuration directory
+        let is_valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+        match is_valid {
+            true => Ok(Self(name)),
+            false => Err(format!(
+                "Unsupported environment \"{s}\". \
+                Use the name of a file in configuration/, e.g. 'local' or 'production'."
+            )),
+        }
+    }
+}
+
+/// Which sources supplied each top-level setting, e.g. `database`, in the order they were applied.
+///
+/// Only names the sources, never the values, so it is safe to log.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConfigSources(BTreeMap<String, Vec<String>>);
+
+impl ConfigSources {
+    /// Notes `name` against every top-level setting `source` has a value for.
+    pub(super) fn record(&mut self, name: &str, source: &dyn Source) -> Result<(), ConfigError> {
+        for key in source.collect()?.keys() {
+            self.add(key, name);
+        }
+        Ok(())
+    }
+
+    /// Notes `name` against the top-level setting that `key`, e.g. `database.password`, is under.
+    pub(super) fn add(&mut self, key: &str, name: &str) {
+        let top_level = key.split('.').next().unwrap_or(key);
+        let names = self.0.entry(top_level.to_string()).or_default();
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+
+    /// The sources of `key`, in the order they were applied.
+    pub fn get(&self, key: &str) -> &[String] {
+        self.0.get(key).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+impl Display for ConfigSources {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (key, names)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{key}: {}", names.join(" < "))?;
+        }
+        Ok(())
+    }
+}
+
+/// `base.yaml`, then `<environment>.yaml`, then the developer's own `<environment>.local.yaml`
+/// if there is one, then `APP_*` variables.
+pub(super) fn load(
+    directory: &Path,
+    environment: &Environment,
+) -> Result<(config::Config, ConfigSources), ConfigError> {
+    let mut sources = ConfigSources::default();
+    let mut builder = config::Config::builder();
+
+    let files = [
+        ("base.yaml".to_string(), true),
+        (format!("{}.yaml", environment.as_str()), true),
+        // not checked in, see .gitignore
+        (format!("{}.local.yaml", environment.as_str()), false),
+    ];
+    for (filename, required) in files {
+        let file = config::File::from(directory.join(&filename)).required(required);
+        sources.record(&filename, &file)?;
+        builder = builder.add_source(file);
+    }
+
+    let variables = config::Environment::with_prefix("APP")
+        .prefix_separator("_")
+        .separator("__");
+    sources.record("APP_* variables", &variables)?;
+    builder = builder.add_source(variables);
+
+    Ok((builder.build()?, sources))
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use uuid::Uuid;
+
+    use super::{load, ConfigSources, Environment};
+
+    #[test]
+    fn any_plain_name_is_an_environment() {
+        for name in ["local", "production", "staging", "test", "eu-west_2"] {
+            assert_ok!(Environment::try_from(name.to_string()));
+        }
+        assert_eq!(
+            Environment::try_from("Staging".to_string())
+                .unwrap()
+                .as_str(),
+            "staging"
+        );
+    }
+
+    #[test]
+    fn names_that_could_leave_the_configuration_directory_are_rejected() {
+        for name in ["", "../secrets", "/etc/passwd", "staging.local", "a b"] {
+            assert_err!(Environment::try_from(name.to_string()));
+        }
+    }
+
+    #[test]
+    fn sources_are_recorded_per_top_level_setting_in_order() {
+        let mut sources = ConfigSources::default();
+        sources.add("database.host", "base.yaml");
+        sources.add("database.port", "base.yaml");
+        sources.add("redis_uri", "base.yaml");
+        sources.add("database.password", "secret provider");
+
+        assert_eq!(sources.get("database"), ["base.yaml", "secret provider"]);
+        assert_eq!(
+            sources.to_string(),
+            "database: base.yaml < secret provider; redis_uri: base.yaml"
+        );
+    }
+
+    #[test]
+    fn the_local_overlay_is_applied_over_the_environment_file() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        std::fs::write(
+            directory.join("base.yaml"),
+            "application:\n  port: 8000\ndatabase:\n  host: base",
+        )
+        .unwrap();
+        std::fs::write(directory.join("staging.yaml"), "database:\n  host: staging").unwrap();
+        let staging = Environment::try_from("staging".to_string()).unwrap();
+
+        let (configuration, sources) = load(&directory, &staging).unwrap();
+        assert_eq!(
+            configuration.get_string("database.host").unwrap(),
+            "staging"
+        );
+        assert_eq!(sources.get("database"), ["base.yaml", "staging.yaml"]);
+        assert_eq!(sources.get("application"), ["base.yaml"]);
+
+        std::fs::write(
+            directory.join("staging.local.yaml"),
+            "database:\n  host: mine",
+        )
+        .unwrap();
+        let (configuration, sources) = load(&directory, &staging).unwrap();
+        assert_eq!(configuration.get_string("database.host").unwrap(), "mine");
+        assert_eq!(
+            sources.get("database"),
+            ["base.yaml", "staging.yaml", "staging.local.yaml"]
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn an_environment_without_a_file_is_an_error() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        std::fs::write(directory.join("base.yaml"), "").unwrap();
+
+        let environment = Environment::try_from("nowhere".to_string()).unwrap();
+        assert_err!(load(&directory, &environment));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
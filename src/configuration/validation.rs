@@ -0,0 +1,251 @@
+use std::fmt::{Debug, Display};
+
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+
+use super::Settings;
+use crate::domain::SubscriberEmail;
+
+/// The session cookies are signed with it, and `cookie::Key` refuses anything shorter.
+const MIN_HMAC_SECRET_LENGTH: usize = 64;
+
+/// One setting that cannot be used as it is.
+pub struct ConfigProblem {
+    /// Where it is in the configuration files, e.g. `email_client.sender_email`.
+    pub key: &'static str,
+    pub message: String,
+}
+
+/// Everything wrong with a configuration, so it can all be fixed in one go.
+pub struct InvalidConfiguration(pub Vec<ConfigProblem>);
+
+impl Display for InvalidConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The configuration is invalid:")?;
+        for problem in &self.0 {
+            write!(f, "\n  {}: {}", problem.key, problem.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for InvalidConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for InvalidConfiguration {}
+
+#[derive(Default)]
+struct Problems(Vec<ConfigProblem>);
+
+impl Problems {
+    fn check(&mut self, key: &'static str, result: Result<(), impl Display>) {
+        if let Err(e) = result {
+            self.0.push(ConfigProblem {
+                key,
+                message: e.to_string(),
+            });
+        }
+    }
+}
+
+fn http_url(url: &str) -> Result<(), String> {
+    let parsed = Url::parse(url).map_err(|e| format!("\"{url}\" is not a valid URL ({e})."))?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => Err(format!("\"{url}\" must be http or https, not {scheme}.")),
+    }
+}
+
+fn hmac_secret(secret: &Secret<String>) -> Result<(), String> {
+    match secret.expose_secret().len() {
+        n if n < MIN_HMAC_SECRET_LENGTH => Err(format!(
+            "must be at least {MIN_HMAC_SECRET_LENGTH} bytes long, not {n}."
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn positive(value: u64) -> Result<(), &'static str> {
+    match value {
+        0 => Err("must be greater than 0."),
+        _ => Ok(()),
+    }
+}
+
+impl Settings {
+    /// Checks everything that would otherwise only fail once it is used, e.g. mid-request.
+    pub fn validate(&self) -> Result<(), InvalidConfiguration> {
+        let mut problems = Problems::default();
+
+        problems.check("application.base_url", http_url(&self.application.base_url));
+        problems.check(
+            "application.hmac_secret",
+            hmac_secret(&self.application.hmac_secret),
+        );
+        for secret in &self.application.previous_hmac_secrets {
+            problems.check("application.previous_hmac_secrets", hmac_secret(secret));
+        }
+        problems.check(
+            "application.shutdown_timeout_seconds",
+            positive(self.application.shutdown_timeout_seconds),
+        );
+
+        problems.check(
+            "email_client.base_url",
+            http_url(&self.email_client.base_url),
+        );
+        problems.check(
+            "email_client.sender_email",
+            SubscriberEmail::parse(self.email_client.sender_email.clone()).map(|_| ()),
+        );
+        problems.check(
+            "email_client.timeout_milliseconds",
+            positive(self.email_client.timeout_milliseconds),
+        );
+
+        problems.check(
+            "redis_uri",
+            redis::Client::open(self.redis_uri.expose_secret().as_str())
+                .map(|_| ())
+                // the message of a malformed URI can include the password
+                .map_err(|_| "is not a valid Redis URI."),
+        );
+
+        problems.check(
+            "session.idle_timeout_seconds",
+            positive(self.session.idle_timeout_seconds),
+        );
+        problems.check(
+            "session.absolute_timeout_seconds",
+            match self.session.absolute_timeout_seconds {
+                n if n < self.session.idle_timeout_seconds => {
+                    Err("must not be shorter than session.idle_timeout_seconds.")
+                }
+                _ => Ok(()),
+            },
+        );
+
+        problems.check(
+            "security_headers",
+            self.security_headers
+                .headers(&self.application.base_url)
+                .map(|_| ()),
+        );
+
+        problems.check(
+            "subscriptions.rate_limit_window_seconds",
+            positive(self.subscriptions.rate_limit_window_seconds),
+        );
+        problems.check(
+            "subscriptions.max_form_age_seconds",
+            match self.subscriptions.max_form_age_seconds {
+                n if n <= self.subscriptions.min_form_fill_seconds => {
+                    Err("must be longer than subscriptions.min_form_fill_seconds.")
+                }
+                _ => Ok(()),
+            },
+        );
+
+        problems.check(
+            "email_validation",
+            self.email_validation.validator().map(|_| ()),
+        );
+
+        problems.check(
+            "worker.concurrency",
+            positive(self.worker.concurrency as u64),
+        );
+        problems.check(
+            "worker.idle_poll_seconds",
+            positive(self.worker.idle_poll_seconds),
+        );
+
+        if let Some(otlp_endpoint) = &self.telemetry.otlp_endpoint {
+            problems.check("telemetry.otlp_endpoint", http_url(otlp_endpoint));
+        }
+
+        match problems.0.is_empty() {
+            true => Ok(()),
+            false => Err(InvalidConfiguration(problems.0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use config::{Config, File, FileFormat};
+    use secrecy::Secret;
+
+    use crate::configuration::Settings;
+
+    fn local_settings() -> Settings {
+        Config::builder()
+            .add_source(File::from_str(
+                include_str!("../../configuration/base.yaml"),
+                FileFormat::Yaml,
+            ))
+            .add_source(File::from_str(
+                include_str!("../../configuration/local.yaml"),
+                FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn the_local_configuration_is_valid() {
+        assert_ok!(local_settings().validate());
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_key() {
+        let mut settings = local_settings();
+        settings.application.hmac_secret = Secret::new("too-short".into());
+        settings.application.previous_hmac_secrets = vec![Secret::new("also-too-short".into())];
+        settings.email_client.sender_email = "not-an-email".into();
+        settings.email_client.timeout_milliseconds = 0;
+        settings.redis_uri = Secret::new("localhost:6379".into());
+
+        let e = assert_err!(settings.validate());
+        let keys: Vec<_> = e.0.iter().map(|p| p.key).collect();
+        assert_eq!(
+            keys,
+            [
+                "application.hmac_secret",
+                "application.previous_hmac_secrets",
+                "email_client.sender_email",
+                "email_client.timeout_milliseconds",
+                "redis_uri",
+            ]
+        );
+        assert!(e
+            .to_string()
+            .contains("application.hmac_secret: must be at least 64 bytes long, not 9."));
+    }
+
+    #[test]
+    fn an_invalid_sender_is_an_error_even_without_validating() {
+        let mut settings = local_settings();
+        settings.email_client.sender_email = "not-an-email".into();
+
+        let e = settings.email_client.client().err().unwrap();
+        assert!(e.to_string().contains("email_client.sender_email"));
+    }
+
+    #[test]
+    fn urls_must_be_http() {
+        let mut settings = local_settings();
+        settings.email_client.base_url = "localhost".into();
+        settings.telemetry.otlp_endpoint = Some("ftp://collector:4317".into());
+
+        let e = assert_err!(settings.validate());
+        let keys: Vec<_> = e.0.iter().map(|p| p.key).collect();
+        assert_eq!(keys, ["email_client.base_url", "telemetry.otlp_endpoint"]);
+    }
+}
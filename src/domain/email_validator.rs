@@ -0,0 +1,191 @@
+use std::{collections::HashSet, sync::Arc};
+
+use trust_dns_resolver::{error::ResolveErrorKind, TokioAsyncResolver};
+
+use super::SubscriberEmail;
+
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// The domains whose addresses have a canonical form of their own when `canonicalize_gmail` is on.
+pub const GMAIL_DOMAINS: [&str; 2] = ["gmail.com", "googlemail.com"];
+
+/// Looks up whether a domain is able to receive email.
+#[async_trait::async_trait]
+pub trait MxResolver: Send + Sync {
+    async fn has_mail_exchanger(&self, domain: &str) -> Result<bool, anyhow::Error>;
+}
+
+pub struct DnsMxResolver(TokioAsyncResolver);
+
+impl DnsMxResolver {
+    pub fn from_system_conf() -> Result<Self, anyhow::Error> {
+        Ok(Self(TokioAsyncResolver::tokio_from_system_conf()?))
+    }
+}
+
+#[async_trait::async_trait]
+impl MxResolver for DnsMxResolver {
+    async fn has_mail_exchanger(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        // the trailing dot stops the resolver from trying local search domains
+        match self.0.mx_lookup(format!("{domain}.")).await {
+            Ok(lookup) => Ok(lookup.iter().next().is_some()),
+            Err(e) => match e.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => Ok(false),
+                _ => Err(e.into()),
+            },
+        }
+    }
+}
+
+/// The checks a [`SubscriberEmail`] goes through before we send anything to it.
+pub struct EmailValidator {
+    canonicalize_gmail: bool,
+    disposable_domains: HashSet<String>,
+    mx_resolver: Option<Arc<dyn MxResolver>>,
+}
+
+impl EmailValidator {
+    /// `extra_disposable_domains` uses the same format as the bundled `disposable_domains.txt`.
+    pub fn new(
+        canonicalize_gmail: bool,
+        extra_disposable_domains: &str,
+        mx_resolver: Option<Arc<dyn MxResolver>>,
+    ) -> Self {
+        let disposable_domains = BUNDLED_DISPOSABLE_DOMAINS
+            .lines()
+            .chain(extra_disposable_domains.lines())
+            .map(|l| l.trim().to_lowercase())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .collect();
+
+        Self {
+            canonicalize_gmail,
+            disposable_domains,
+            mx_resolver,
+        }
+    }
+
+    /// Sets the form `email` is deduped and rate limited by. [`Self::validate`] does it too.
+    pub fn canonicalize(&self, email: SubscriberEmail) -> SubscriberEmail {
+        if self.canonicalize_gmail {
+            canonicalize_gmail(email)
+        } else {
+            email
+        }
+    }
+
+    pub async fn validate(&self, email: SubscriberEmail) -> Result<SubscriberEmail, String> {
+        let email = self.canonicalize(email);
+
+        if self.is_disposable(email.domain()) {
+            return Err("The address uses a disposable email provider.".into());
+        }
+
+        if let Some(mx_resolver) = &self.mx_resolver {
+            match mx_resolver.has_mail_exchanger(email.domain()).await {
+                Ok(true) => {}
+                Ok(false) => return Err("The domain of the address cannot receive email.".into()),
+                // don't turn people away because DNS is having a bad day
+                Err(e) => tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to look up MX records. Accepting the address."
+                ),
+            }
+        }
+
+        Ok(email)
+    }
+
+    fn is_disposable(&self, domain: &str) -> bool {
+        std::iter::successors(Some(domain), |d| {
+            d.split_once('.').map(|(_, parent)| parent)
+        })
+        .any(|d| self.disposable_domains.contains(d))
+    }
+}
+
+/// Gmail ignores dots and `+tags` in the local part, and `googlemail.com` is an alias.
+///
+/// Only the canonical form changes: mail still goes to the address as typed, `+tag` and all,
+/// so the subscriber's filters keep working.
+fn canonicalize_gmail(email: SubscriberEmail) -> SubscriberEmail {
+    if !GMAIL_DOMAINS.contains(&email.domain()) {
+        return email;
+    }
+
+    let local_part = email.local_part();
+    let local_part = local_part
+        .split_once('+')
+        .map(|(l, _)| l)
+        .unwrap_or(local_part)
+        .replace('.', "")
+        .to_lowercase();
+    email.with_canonical(format!("{local_part}@gmail.com"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use claim::{assert_err, assert_ok};
+
+    use super::{EmailValidator, MxResolver};
+    use crate::domain::SubscriberEmail;
+
+    struct FakeMxResolver(HashSet<&'static str>);
+
+    #[async_trait::async_trait]
+    impl MxResolver for FakeMxResolver {
+        async fn has_mail_exchanger(&self, domain: &str) -> Result<bool, anyhow::Error> {
+            Ok(self.0.contains(domain))
+        }
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn bundled_disposable_domains_and_their_subdomains_are_rejected() {
+        let validator = EmailValidator::new(false, "", None);
+        assert_err!(validator.validate(email("ursula@mailinator.com")).await);
+        assert_err!(validator.validate(email("ursula@eu.Mailinator.com")).await);
+        assert_ok!(validator.validate(email("ursula@example.com")).await);
+    }
+
+    #[tokio::test]
+    async fn extra_disposable_domains_are_rejected() {
+        let validator = EmailValidator::new(false, "# ours\nthrowaway.example\n", None);
+        assert_err!(validator.validate(email("ursula@throwaway.example")).await);
+    }
+
+    #[tokio::test]
+    async fn gmail_addresses_are_canonicalized_when_enabled() {
+        let validator = EmailValidator::new(true, "", None);
+        let validated = validator
+            .validate(email("Ursula.Le.Guin+news@googlemail.com"))
+            .await
+            .unwrap();
+        assert_eq!(validated.canonical(), "ursulaleguin@gmail.com");
+        assert_eq!(validated.as_ref(), "Ursula.Le.Guin+news@googlemail.com");
+    }
+
+    #[tokio::test]
+    async fn other_addresses_are_left_alone_by_gmail_canonicalization() {
+        let validator = EmailValidator::new(true, "", None);
+        let validated = validator
+            .validate(email("Ursula.Le.Guin+news@example.com"))
+            .await
+            .unwrap();
+        assert_eq!(validated.canonical(), "ursula.le.guin+news@example.com");
+    }
+
+    #[tokio::test]
+    async fn domains_without_mail_exchangers_are_rejected() {
+        let resolver = FakeMxResolver(HashSet::from(["example.com"]));
+        let validator = EmailValidator::new(false, "", Some(Arc::new(resolver)));
+        assert_ok!(validator.validate(email("ursula@example.com")).await);
+        assert_err!(validator.validate(email("ursula@no-mail.example")).await);
+    }
+}
@@ -1,7 +1,9 @@
+pub mod email_validator;
 pub mod new_subscriber;
 pub mod subscriber_email;
 pub mod subscriber_name;
 
+pub use email_validator::{DnsMxResolver, EmailValidator, MxResolver, GMAIL_DOMAINS};
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
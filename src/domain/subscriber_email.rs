@@ -1,27 +1,66 @@
 use validator::validate_email;
 
 #[derive(Debug)]
-pub struct SubscriberEmail(String);
+pub struct SubscriberEmail {
+    /// As the subscriber typed it, and what we send to.
+    address: String,
+    canonical: String,
+}
 
 impl SubscriberEmail {
+    /// Validates `s` and normalizes its domain to lowercase ASCII (IDNA).
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if validate_email(&s) {
-            Ok(Self(s))
-        } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+        if !validate_email(&s) {
+            return Err("The address is not a valid subscriber email.".into());
         }
+
+        let (local_part, domain) = s
+            .rsplit_once('@')
+            .ok_or("The address is not a valid subscriber email.")?;
+        let domain = idna::domain_to_ascii(domain)
+            .map_err(|_| "The address does not have a valid domain.")?;
+        let address = format!("{local_part}@{domain}");
+        Ok(Self {
+            canonical: address.to_lowercase(),
+            address,
+        })
+    }
+
+    /// The form used to tell addresses apart, e.g. `Ursula@Domain.com` and `ursula@domain.com`
+    /// belong to the same subscriber. Queries should match on this rather than the address as typed.
+    pub fn canonical(&self) -> &str {
+        &self.canonical
+    }
+
+    /// Keeps the address as it is, but dedupes it as `canonical`, e.g. with provider specific rules.
+    pub fn with_canonical(self, canonical: String) -> Self {
+        Self { canonical, ..self }
+    }
+
+    pub fn local_part(&self) -> &str {
+        self.address
+            .rsplit_once('@')
+            .map(|(l, _)| l)
+            .unwrap_or_default()
+    }
+
+    pub fn domain(&self) -> &str {
+        self.address
+            .rsplit_once('@')
+            .map(|(_, d)| d)
+            .unwrap_or_default()
     }
 }
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.address
     }
 }
 
 impl std::fmt::Display for SubscriberEmail {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.address.fmt(f)
     }
 }
 
@@ -64,4 +103,22 @@ mod tests {
         let email = String::from("@domain.com");
         assert_err!(SubscriberEmail::parse(email));
     }
+
+    #[test]
+    fn domain_is_lowercased_but_local_part_is_kept() {
+        let email = SubscriberEmail::parse("Ursula@Domain.COM".into()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula@domain.com");
+    }
+
+    #[test]
+    fn canonical_form_ignores_case() {
+        let email = SubscriberEmail::parse("Ursula@Domain.COM".into()).unwrap();
+        assert_eq!(email.canonical(), "ursula@domain.com");
+    }
+
+    #[test]
+    fn internationalized_domains_are_converted_to_ascii() {
+        let email = SubscriberEmail::parse("ursula@bücher.example".into()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+    }
 }
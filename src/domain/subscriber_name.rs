@@ -12,7 +12,7 @@ impl SubscriberName {
         let contains_forbidden_characters = s.chars().any(|g| forbidden_characters.contains(&g));
 
         if is_empty_or_whitespace || is_too_long | contains_forbidden_characters {
-            Err(format!("{} is not a valid subscriber name.", s))
+            Err("The name is not a valid subscriber name.".into())
         } else {
             Ok(Self(s))
         }
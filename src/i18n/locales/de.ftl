@@ -0,0 +1,19 @@
+language-name = Deutsch
+
+home-title = Startseite
+home-welcome = Willkommen bei unserem Newsletter!
+home-name-label = Name
+home-name-placeholder = Ihr Name
+home-email-label = E-Mail
+home-language-label = Sprache
+home-honeypot-label = Dieses Feld bitte leer lassen
+home-subscribe-button = Abonnieren
+
+confirmation-email-subject = Willkommen!
+confirmation-email-html = Willkommen bei unserem Newsletter!<br />Klicken Sie <a href="{ $link }">hier</a>, um Ihr Abonnement zu bestätigen.
+confirmation-email-text =
+    Willkommen bei unserem Newsletter!
+    Besuchen Sie { $link }, um Ihr Abonnement zu bestätigen.
+
+confirmed-title = Abonnement bestätigt
+confirmed-body = Danke für Ihre Bestätigung, Sie erhalten unsere nächste Ausgabe.
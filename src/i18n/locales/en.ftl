@@ -0,0 +1,19 @@
+language-name = English
+
+home-title = Home
+home-welcome = Welcome to our newsletter!
+home-name-label = Name
+home-name-placeholder = Your Name
+home-email-label = Email
+home-language-label = Language
+home-honeypot-label = Leave this field empty
+home-subscribe-button = Subscribe
+
+confirmation-email-subject = Welcome!
+confirmation-email-html = Welcome to our newsletter!<br />Click <a href="{ $link }">here</a> to confirm your subscription.
+confirmation-email-text =
+    Welcome to our newsletter!
+    Visit { $link } to confirm your subscription.
+
+confirmed-title = Subscription confirmed
+confirmed-body = Thanks for confirming, you will receive our next issue.
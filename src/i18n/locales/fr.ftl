@@ -0,0 +1,19 @@
+language-name = Français
+
+home-title = Accueil
+home-welcome = Bienvenue sur notre newsletter !
+home-name-label = Nom
+home-name-placeholder = Votre nom
+home-email-label = E-mail
+home-language-label = Langue
+home-honeypot-label = Laissez ce champ vide
+home-subscribe-button = S'abonner
+
+confirmation-email-subject = Bienvenue !
+confirmation-email-html = Bienvenue sur notre newsletter !<br />Cliquez <a href="{ $link }">ici</a> pour confirmer votre abonnement.
+confirmation-email-text =
+    Bienvenue sur notre newsletter !
+    Rendez-vous sur { $link } pour confirmer votre abonnement.
+
+confirmed-title = Abonnement confirmé
+confirmed-body = Merci pour votre confirmation, vous recevrez notre prochain numéro.
@@ -0,0 +1,151 @@
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource};
+use fluent_langneg::{accepted_languages, negotiate_languages, NegotiationStrategy};
+use unic_langid::LanguageIdentifier;
+
+/// Message catalogs bundled with the binary, the first one being the fallback.
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("locales/en.ftl")),
+    ("de", include_str!("locales/de.ftl")),
+    ("fr", include_str!("locales/fr.ftl")),
+];
+
+/// Renders subscriber-facing messages in the subscriber's language.
+pub struct Localizer {
+    locales: Vec<LanguageIdentifier>,
+    bundles: Vec<FluentBundle<FluentResource>>,
+}
+
+impl Localizer {
+    pub fn new() -> Self {
+        let (locales, bundles) = CATALOGS
+            .iter()
+            .map(|(locale, source)| {
+                let locale: LanguageIdentifier = locale.parse().expect("Invalid catalog locale.");
+                let resource = FluentResource::try_new(source.to_string())
+                    .unwrap_or_else(|(_, e)| panic!("Invalid {locale} catalog: {e:?}"));
+                let mut bundle = FluentBundle::new_concurrent(vec![locale.clone()]);
+                // unicode isolation marks around arguments would end up inside links in emails
+                bundle.set_use_isolating(false);
+                bundle
+                    .add_resource(resource)
+                    .unwrap_or_else(|e| panic!("Invalid {locale} catalog: {e:?}"));
+                (locale, bundle)
+            })
+            .unzip();
+
+        Self { locales, bundles }
+    }
+
+    pub fn default_locale(&self) -> &LanguageIdentifier {
+        &self.locales[0]
+    }
+
+    pub fn available_locales(&self) -> &[LanguageIdentifier] {
+        &self.locales
+    }
+
+    /// Picks the best supported locale for an `Accept-Language` style list, e.g. `fr-CA,fr;q=0.8`.
+    /// A single tag such as `de` works too.
+    pub fn negotiate(&self, requested: &str) -> LanguageIdentifier {
+        let requested = accepted_languages::parse(requested);
+        negotiate_languages(
+            &requested,
+            &self.locales,
+            Some(self.default_locale()),
+            NegotiationStrategy::Lookup,
+        )
+        .first()
+        .map(|l| (*l).clone())
+        .unwrap_or_else(|| self.default_locale().clone())
+    }
+
+    /// Formats message `id` in `locale`, falling back to the default catalog.
+    pub fn format(
+        &self,
+        locale: &LanguageIdentifier,
+        id: &str,
+        args: Option<&FluentArgs>,
+    ) -> String {
+        let bundle = self
+            .locales
+            .iter()
+            .position(|l| l == locale)
+            .map(|i| &self.bundles[i])
+            .unwrap_or(&self.bundles[0]);
+
+        let pattern = match bundle.get_message(id).and_then(|m| m.value()) {
+            Some(pattern) => pattern,
+            None => {
+                tracing::error!(message_id = %id, locale = %locale, "Missing localized message.");
+                return id.to_string();
+            }
+        };
+
+        let mut errors = vec![];
+        let message = bundle.format_pattern(pattern, args, &mut errors);
+        if !errors.is_empty() {
+            tracing::error!(message_id = %id, locale = %locale, errors = ?errors, "Failed to format localized message.");
+        }
+        message.into_owned()
+    }
+}
+
+impl Default for Localizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{Localizer, CATALOGS};
+
+    fn message_ids(source: &str) -> HashSet<&str> {
+        source
+            .lines()
+            .filter_map(|l| l.split_once(" ="))
+            .map(|(id, _)| id)
+            .filter(|id| !id.starts_with(char::is_whitespace))
+            .collect()
+    }
+
+    #[test]
+    fn every_catalog_has_the_same_messages() {
+        let reference = message_ids(CATALOGS[0].1);
+        for (locale, source) in CATALOGS {
+            assert_eq!(message_ids(source), reference, "{locale} catalog differs");
+        }
+    }
+
+    #[test]
+    fn accept_language_headers_are_negotiated() {
+        let localizer = Localizer::new();
+        assert_eq!(
+            localizer.negotiate("fr-CA,fr;q=0.8,en;q=0.5").to_string(),
+            "fr"
+        );
+        assert_eq!(localizer.negotiate("de").to_string(), "de");
+    }
+
+    #[test]
+    fn unsupported_languages_fall_back_to_the_default() {
+        let localizer = Localizer::new();
+        assert_eq!(localizer.negotiate("ja-JP").to_string(), "en");
+        assert_eq!(localizer.negotiate("").to_string(), "en");
+    }
+
+    #[test]
+    fn messages_are_formatted_with_arguments() {
+        let localizer = Localizer::new();
+        let mut args = fluent_bundle::FluentArgs::new();
+        args.set("link", "https://example.com");
+        let text = localizer.format(
+            &"de".parse().unwrap(),
+            "confirmation-email-text",
+            Some(&args),
+        );
+        assert!(text.contains("Besuchen Sie https://example.com, um"));
+    }
+}
@@ -0,0 +1,5 @@
+mod localizer;
+
+pub use fluent_bundle::FluentArgs;
+pub use localizer::Localizer;
+pub use unic_langid::LanguageIdentifier;
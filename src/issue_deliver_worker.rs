@@ -1,97 +1,265 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use sqlx::{PgPool, Postgres, Transaction};
+use sqlx::{postgres::PgListener, PgPool, Postgres, Transaction};
+use tokio::{
+    sync::{watch, Mutex},
+    time::Instant,
+};
 use tracing::{field::display, Span};
 use uuid::Uuid;
 
 use crate::{
-    configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
+    configuration::Settings,
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    metrics::{record_email, EmailOutcome},
     startup::get_connection_pool,
+    telemetry::continue_trace,
+    tracking::IssueTracker,
 };
 
+/// The Postgres channel `enqueue_delivery_tasks` notifies when it commits new tasks.
+pub const NEW_TASKS_CHANNEL: &str = "issue_delivery_queue";
+
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
 }
 
-pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+/// Spaces sends out evenly so that all workers together stay under a fixed rate.
+pub struct SendRateLimiter {
+    period: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl SendRateLimiter {
+    pub fn per_second(max_sends: u32) -> Self {
+        Self {
+            period: Duration::from_secs(1) / max_sends.max(1),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until we are allowed to send another email.
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.period;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+/// Runs the delivery workers until `shutdown` flips to `true`.
+/// Tasks already being delivered are finished first.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    shutdown: watch::Receiver<bool>,
+) -> Result<(), anyhow::Error> {
     let connection_pool = get_connection_pool(&configuration.database);
-    let email_client = configuration.email_client.client();
-    worker_loop(connection_pool, email_client).await
+    let signing_keys = configuration.application.signing_keys();
+    let issue_tracker = Arc::new(IssueTracker::new(
+        configuration.application.base_url,
+        signing_keys,
+        configuration.tracking.enabled,
+    ));
+    let email_client = Arc::new(configuration.email_client.client()?);
+    let rate_limiter = configuration.worker.send_rate_limiter().map(Arc::new);
+    let new_tasks = listen_for_new_tasks(connection_pool.clone());
+
+    let workers: Vec<_> = (0..configuration.worker.concurrency.max(1))
+        .map(|_| {
+            tokio::spawn(worker_loop(
+                connection_pool.clone(),
+                email_client.clone(),
+                issue_tracker.clone(),
+                rate_limiter.clone(),
+                new_tasks.clone(),
+                shutdown.clone(),
+                configuration.worker.idle_poll_interval(),
+            ))
+        })
+        .collect();
+    for worker in workers {
+        worker.await??;
+    }
+    Ok(())
+}
+
+/// Relays notifications on [`NEW_TASKS_CHANNEL`] to the workers waiting on an empty queue.
+fn listen_for_new_tasks(pool: PgPool) -> watch::Receiver<()> {
+    let (sender, receiver) = watch::channel(());
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = relay_notifications(&pool, &sender).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Stopped listening for new delivery tasks. Reconnecting."
+                );
+                // tasks may have been enqueued while we weren't listening
+                sender.send_replace(());
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    });
+    receiver
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
+async fn relay_notifications(pool: &PgPool, sender: &watch::Sender<()>) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(NEW_TASKS_CHANNEL).await?;
     loop {
-        match try_execute_task(&pool, &email_client).await {
+        listener.recv().await?;
+        sender.send_replace(());
+    }
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    issue_tracker: Arc<IssueTracker>,
+    rate_limiter: Option<Arc<SendRateLimiter>>,
+    mut new_tasks: watch::Receiver<()>,
+    mut shutdown: watch::Receiver<bool>,
+    idle_poll_interval: Duration,
+) -> Result<(), anyhow::Error> {
+    loop {
+        // anything enqueued from here on wakes us up once the queue looks empty
+        new_tasks.borrow_and_update();
+        if let Some(rate_limiter) = &rate_limiter {
+            tokio::select! {
+                _ = rate_limiter.acquire() => {}
+                _ = shutdown.changed() => {}
+            }
+        }
+        if *shutdown.borrow() {
+            return Ok(());
+        }
+        match try_execute_task(&pool, &email_client, &issue_tracker).await {
             Err(_) => {
                 tokio::time::sleep(Duration::from_secs(1)).await;
             }
             Ok(ExecutionOutcome::EmptyQueue) => {
-                tokio::time::sleep(Duration::from_secs(10)).await;
+                // polling as well covers notifications lost while reconnecting
+                tokio::select! {
+                    Ok(()) = new_tasks.changed() => {}
+                    _ = tokio::time::sleep(idle_poll_interval) => {}
+                    _ = shutdown.changed() => {}
+                }
             }
             Ok(ExecutionOutcome::TaskCompleted) => {}
         }
     }
 }
 
-#[tracing::instrument(skip_all, fields(newsletter_issue_id=tracing::field::Empty, subscriber_email=tracing::field::Empty), err)]
+#[tracing::instrument(skip_all, fields(newsletter_issue_id=tracing::field::Empty, subscriber_email=tracing::field::Empty, request_id=tracing::field::Empty), err)]
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    issue_tracker: &IssueTracker,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
     match dequeue_task(pool).await? {
-        Some((transaction, issue_id, email)) => {
+        Some((transaction, task)) => {
+            let (issue_id, email) = (task.newsletter_issue_id, task.subscriber_email);
+            if let Some(traceparent) = task.traceparent {
+                continue_trace(&traceparent);
+            }
             Span::current()
-                .record("newsletter_issue_id", &display(issue_id))
-                .record("subscriber_email", &display(&email));
-
-            match SubscriberEmail::parse(email.clone()) {
-                Ok(email) => {
-                    let issue = get_issue(pool, issue_id).await?;
-                    if let Err(e) = email_client
-                        .send_email(
-                            &email,
-                            &issue.title,
-                            &issue.html_content,
-                            &issue.text_content,
-                        )
-                        .await
-                    {
-                        tracing::error!(
-                            error.cause_chain = ?e,
-                            error.message = %e,
-                            "Failed to deliver issue to a confirmed subscriber. \
-                            Skipping."
-                        )
+                .record("newsletter_issue_id", display(issue_id))
+                .record("subscriber_email", display(&email));
+            if let Some(request_id) = &task.request_id {
+                Span::current().record("request_id", display(request_id));
+            }
+
+            // on error the transaction is rolled back, leaving the task for another attempt
+            let outcome = deliver_task(
+                pool,
+                email_client,
+                issue_tracker,
+                transaction,
+                issue_id,
+                &email,
+            )
+            .await
+            .inspect_err(|_| record_email(issue_id, EmailOutcome::Retried))?;
+            record_email(issue_id, outcome);
+            Ok(ExecutionOutcome::TaskCompleted)
+        }
+        None => Ok(ExecutionOutcome::EmptyQueue),
+    }
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/// Sends one queued email, and removes it from the queue.
+async fn deliver_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    issue_tracker: &IssueTracker,
+    transaction: PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+) -> Result<EmailOutcome, anyhow::Error> {
+    let outcome = match SubscriberEmail::parse(email.to_string()) {
+        Ok(email) => {
+            let issue = get_issue(pool, issue_id).await?;
+            let html_content =
+                match tracked_subscriber_id(pool, &issue, issue_tracker, &email).await? {
+                    Some(subscriber_id) => {
+                        issue_tracker.instrument(issue_id, subscriber_id, &issue.html_content)
                     }
-                }
+                    None => issue.html_content,
+                };
+            match email_client
+                .send_email(&email, &issue.title, &html_content, &issue.text_content)
+                .await
+            {
+                Ok(()) => EmailOutcome::Sent,
                 Err(e) => {
                     tracing::error!(
                         error.cause_chain = ?e,
                         error.message = %e,
-                        "Skipping a confirmed subscriber. \
-                        Their stored contact details are invalid."
-                    )
+                        "Failed to deliver issue to a confirmed subscriber. \
+                        Skipping."
+                    );
+                    EmailOutcome::Failed
                 }
             }
-            delete_task(transaction, issue_id, &email).await?;
-            Ok(ExecutionOutcome::TaskCompleted)
         }
-        None => Ok(ExecutionOutcome::EmptyQueue),
-    }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Skipping a confirmed subscriber. \
+                Their stored contact details are invalid."
+            );
+            EmailOutcome::Failed
+        }
+    };
+    delete_task(transaction, issue_id, email).await?;
+    Ok(outcome)
 }
 
-type PgTransaction = Transaction<'static, Postgres>;
+struct QueuedTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    /// Of the request that published the issue, if it was being traced.
+    traceparent: Option<String>,
+    /// Of the request that published the issue.
+    request_id: Option<String>,
+}
 
 #[tracing::instrument(skip_all)]
-async fn dequeue_task(
-    pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, QueuedTask)>, anyhow::Error> {
     let mut transaction = pool.begin().await?;
 
-    let r = sqlx::query!(
+    let task = sqlx::query_as!(
+        QueuedTask,
         r#"
-        SELECT newsletter_issue_id, subscriber_email
+        SELECT newsletter_issue_id, subscriber_email, traceparent, request_id
         FROM issue_delivery_queue
         FOR UPDATE
         SKIP LOCKED
@@ -101,21 +269,14 @@ async fn dequeue_task(
     .fetch_optional(&mut transaction)
     .await?;
 
-    if let Some(r) = r {
-        Ok(Some((
-            transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
-        )))
-    } else {
-        Ok(None)
-    }
+    Ok(task.map(|task| (transaction, task)))
 }
 
 struct NewsletterIssue {
     title: String,
     text_content: String,
     html_content: String,
+    tracking_enabled: bool,
 }
 
 #[tracing::instrument(skip_all)]
@@ -123,7 +284,7 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
     let issue = sqlx::query_as!(
         NewsletterIssue,
         r#"
-        SELECT title, text_content, html_content
+        SELECT title, text_content, html_content, tracking_enabled
         FROM newsletter_issues
         WHERE
             newsletter_issue_id = $1
@@ -135,6 +296,31 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
     Ok(issue)
 }
 
+/// The subscriber to tag tracking links with, if this delivery should be tracked at all.
+#[tracing::instrument(skip_all)]
+async fn tracked_subscriber_id(
+    pool: &PgPool,
+    issue: &NewsletterIssue,
+    issue_tracker: &IssueTracker,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    if !(issue.tracking_enabled && issue_tracker.is_enabled()) {
+        return Ok(None);
+    }
+
+    let r = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE email = $1
+        "#,
+        email.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(r.map(|r| r.id))
+}
+
 #[tracing::instrument(skip_all)]
 async fn delete_task(
     mut transaction: PgTransaction,
@@ -157,3 +343,23 @@ async fn delete_task(
     transaction.commit().await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::SendRateLimiter;
+
+    #[tokio::test]
+    async fn sends_are_spaced_out_to_the_configured_rate() {
+        let rate_limiter = SendRateLimiter::per_second(20);
+        let start = Instant::now();
+        for _ in 0..5 {
+            rate_limiter.acquire().await;
+        }
+        // the first send goes out immediately, the other four 50ms apart
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
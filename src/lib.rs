@@ -1,10 +1,20 @@
+pub mod anti_abuse;
 pub mod authentication;
+pub mod client_ip;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod i18n;
 pub mod idempotency;
 pub mod issue_deliver_worker;
+pub mod metrics;
+pub mod migrations;
+pub mod redaction;
+pub mod request_id;
 pub mod routes;
+pub mod security_headers;
 pub mod session_state;
+pub mod signing_keys;
 pub mod startup;
 pub mod telemetry;
+pub mod tracking;
@@ -1,29 +1,265 @@
 use std::fmt::{Debug, Display};
+use std::io::BufRead;
+use std::net::TcpListener;
 
-use tokio::task::JoinError;
-use zero2prod::startup::Application;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use secrecy::Secret;
+use tokio::sync::watch;
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::Instant;
+use zero2prod::authentication::{create_user, reset_password};
+use zero2prod::configuration::Settings;
+use zero2prod::migrations::run_migrations;
+use zero2prod::startup::{get_connection_pool, Application, MetricsServer};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 use zero2prod::{configuration::get_configuration, issue_deliver_worker::run_worker_until_stopped};
 
+#[derive(Parser)]
+#[command(version, about = "A newsletter delivery service")]
+struct Cli {
+    /// Runs both the API and the delivery worker if omitted.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the HTTP API.
+    Serve,
+    /// Deliver queued newsletter issues.
+    Worker,
+    /// Apply pending database migrations.
+    Migrate,
+    /// Create an admin user and print its password.
+    CreateAdmin {
+        username: String,
+        /// Read the password from stdin instead of generating one.
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Replace a user's password with a generated one that must be changed on next login.
+    ResetPassword { username: String },
+    /// Load the configuration and report whether it is usable.
+    CheckConfig,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+    let cli = Cli::parse();
+
+    let configuration = get_configuration().context("Failed to load config.")?;
+    configuration.validate()?;
+
+    let tracer = configuration.telemetry.tracer("zero2prod")?;
+    let subscriber = get_subscriber(
+        "zero2prod".into(),
+        "info".into(),
+        std::io::stdout,
+        tracer,
+        configuration.telemetry.redaction,
+    );
     init_subscriber(subscriber);
+    tracing::info!(sources = %configuration.sources, "Loaded the configuration");
 
-    let configuration = get_configuration().expect("Failed to load config.");
+    let outcome = match cli.command {
+        None => run_until_stopped(configuration, true, true).await,
+        Some(Command::Serve) => run_until_stopped(configuration, true, false).await,
+        Some(Command::Worker) => run_until_stopped(configuration, false, true).await,
+        Some(Command::Migrate) => Ok(run_migrations(&configuration.database).await?),
+        Some(Command::CreateAdmin {
+            username,
+            password_stdin,
+        }) => create_admin(configuration, &username, password_stdin).await,
+        Some(Command::ResetPassword { username }) => {
+            let password = generate_password();
+            let pool = get_connection_pool(&configuration.database);
+            reset_password(&username, Secret::new(password.clone()), &pool).await?;
+            println!("Reset the password of {username}.");
+            println!("Password: {password}");
+            Ok(())
+        }
+        Some(Command::CheckConfig) => check_config(),
+    };
+
+    // flushes the spans still waiting to be exported, which blocks
+    tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await?;
+    outcome
+}
+
+/// Runs the API and/or the worker until a shutdown signal, or until one of them exits.
+///
+/// A worker on its own gets a server for its metrics instead of the API, if it has a port for it.
+async fn run_until_stopped(configuration: Settings, serve: bool, work: bool) -> anyhow::Result<()> {
+    let shutdown_timeout = configuration.application.shutdown_timeout();
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
 
-    let application = Application::build(configuration.clone()).await?;
-    let application_task = tokio::spawn(application.run_until_stopped());
-    let worker = tokio::spawn(run_worker_until_stopped(configuration));
+    let (server_name, server_handle, mut application_task) = if serve {
+        let application = Application::build(configuration.clone()).await?;
+        (
+            "API",
+            Some(application.server_handle()),
+            Some(tokio::spawn(application.run_until_stopped())),
+        )
+    } else if let Some(port) = configuration.worker.metrics_port.filter(|_| work) {
+        let listener = TcpListener::bind(format!("{}:{port}", configuration.application.host))?;
+        let metrics_server = MetricsServer::build(
+            listener,
+            get_connection_pool(&configuration.database),
+            shutdown_timeout,
+        )?;
+        (
+            "Metrics server",
+            Some(metrics_server.server_handle()),
+            Some(tokio::spawn(metrics_server.run_until_stopped())),
+        )
+    } else {
+        ("API", None, None)
+    };
+    let mut worker = work.then(|| {
+        tokio::spawn(run_worker_until_stopped(
+            configuration.clone(),
+            shutdown_receiver,
+        ))
+    });
 
+    // whichever comes first, a signal or either half giving up, brings the other half down
     tokio::select! {
-        o = application_task => report_exit("API", o),
-        o = worker => report_exit("Background worker", o),
+        _ = shutdown_signal() => {}
+        o = join(&mut application_task) => {
+            report_exit(server_name, o);
+            application_task = None;
+        }
+        o = join(&mut worker) => {
+            report_exit("Background worker", o);
+            worker = None;
+        }
+    };
+
+    tracing::info!("Shutting down");
+    if let Some(server_handle) = server_handle {
+        tokio::spawn(server_handle.stop(true));
+    }
+    shutdown_sender.send_replace(true);
+
+    let deadline = Instant::now() + shutdown_timeout;
+    tokio::join!(
+        wait_for_exit(server_name, application_task, deadline),
+        wait_for_exit("Background worker", worker, deadline),
+    );
+
+    Ok(())
+}
+
+async fn create_admin(
+    configuration: Settings,
+    username: &str,
+    password_stdin: bool,
+) -> anyhow::Result<()> {
+    let (password, generated) = if password_stdin {
+        let mut password = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut password)
+            .context("Failed to read the password from stdin.")?;
+        (password.trim_end_matches(['\r', '\n']).to_string(), false)
+    } else {
+        (generate_password(), true)
     };
+    if password.is_empty() {
+        anyhow::bail!("The password cannot be empty.");
+    }
+
+    let pool = get_connection_pool(&configuration.database);
+    // a generated password has been shown on screen, so it is only good for one login
+    create_user(username, Secret::new(password.clone()), generated, &pool).await?;
 
+    println!("Created admin user {username}.");
+    if generated {
+        println!("Password: {password}");
+    }
+    Ok(())
+}
+
+fn generate_password() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(24)
+        .collect()
+}
+
+/// Problems have been reported by `Settings::validate` by the time this runs.
+fn check_config() -> anyhow::Result<()> {
+    println!("Configuration is valid.");
     Ok(())
 }
 
+/// Resolves on SIGTERM or SIGINT.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => report_signal_error(e).await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        r = tokio::signal::ctrl_c() => {
+            if let Err(e) = r {
+                report_signal_error(e).await;
+            }
+        }
+        _ = terminate => {}
+    }
+    tracing::info!("Received a shutdown signal");
+}
+
+/// Keeps running without that signal rather than shutting down straight away.
+async fn report_signal_error(e: std::io::Error) {
+    tracing::error!(
+        error.cause_chain = ?e,
+        error.message = %e,
+        "Failed to listen for shutdown signals"
+    );
+    std::future::pending().await
+}
+
+/// Waits for a task if it was started at all.
+async fn join<T>(task: &mut Option<JoinHandle<T>>) -> Result<T, JoinError> {
+    match task {
+        Some(task) => task.await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits for a task that has been asked to stop, aborting it if it is still running by `deadline`.
+async fn wait_for_exit<E: Debug + Display>(
+    task_name: &str,
+    task: Option<JoinHandle<Result<(), E>>>,
+    deadline: Instant,
+) {
+    let mut task = match task {
+        Some(task) => task,
+        None => return,
+    };
+    let outcome = match tokio::time::timeout_at(deadline, &mut task).await {
+        Ok(outcome) => outcome,
+        Err(_) => {
+            tracing::warn!("{} did not stop before the shutdown deadline", task_name);
+            task.abort();
+            task.await
+        }
+    };
+    report_exit(task_name, outcome);
+}
+
 fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
     match outcome {
         Ok(Ok(())) => {
@@ -0,0 +1,193 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use prometheus::{
+    proto::MetricFamily, Encoder, Histogram, HistogramOpts, HistogramTimer, HistogramVec,
+    IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Shared by the API and the delivery worker when they run in the same process.
+static METRICS: Lazy<Metrics> =
+    Lazy::new(|| Metrics::new().expect("Failed to register the metrics."));
+
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    emails: IntCounterVec,
+    password_verification_duration: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Result<Self, prometheus::Error> {
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled."),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests.",
+            ),
+            &["method", "route"],
+        )?;
+        let emails = IntCounterVec::new(
+            Opts::new("newsletter_emails_total", "Newsletter emails by outcome."),
+            &["newsletter_issue_id", "outcome"],
+        )?;
+        let password_verification_duration = Histogram::with_opts(HistogramOpts::new(
+            "password_verification_duration_seconds",
+            "Time taken to check a password against its Argon2 hash.",
+        ))?;
+
+        let registry = Registry::new();
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(emails.clone()))?;
+        registry.register(Box::new(password_verification_duration.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            emails,
+            password_verification_duration,
+        })
+    }
+}
+
+pub enum EmailOutcome {
+    Sent,
+    /// Given up on, e.g. rejected by the email API.
+    Failed,
+    /// Put back in the queue to be tried again.
+    Retried,
+}
+
+impl EmailOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EmailOutcome::Sent => "sent",
+            EmailOutcome::Failed => "failed",
+            EmailOutcome::Retried => "retried",
+        }
+    }
+}
+
+pub fn record_email(newsletter_issue_id: Uuid, outcome: EmailOutcome) {
+    METRICS
+        .emails
+        .with_label_values(&[&newsletter_issue_id.to_string(), outcome.as_str()])
+        .inc();
+}
+
+/// Times a password check until the timer is dropped.
+pub fn time_password_verification() -> HistogramTimer {
+    METRICS.password_verification_duration.start_timer()
+}
+
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    // the pattern rather than the path, so every newsletter id doesn't get its own series
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".into());
+    let start = Instant::now();
+
+    let response = next.call(req).await;
+    let status = match &response {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    METRICS
+        .http_requests
+        .with_label_values(&[&method, &route, status.as_str()])
+        .inc();
+    METRICS
+        .http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    response
+}
+
+/// All metrics in the Prometheus text format, with the pool and queue sampled just now.
+pub async fn gather(pool: &PgPool) -> Result<String, anyhow::Error> {
+    let queue_depth = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM issue_delivery_queue
+        "#
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the queued deliveries.")?;
+
+    // sampled into a registry of their own, as each app (e.g. in tests) has its own pool
+    let sampled = Registry::new();
+    let db_pool_connections = IntGaugeVec::new(
+        Opts::new(
+            "db_pool_connections",
+            "Connections held by the Postgres pool.",
+        ),
+        &["state"],
+    )?;
+    let idle = pool.num_idle() as i64;
+    db_pool_connections.with_label_values(&["idle"]).set(idle);
+    db_pool_connections
+        .with_label_values(&["in_use"])
+        .set(i64::from(pool.size()) - idle);
+    sampled.register(Box::new(db_pool_connections))?;
+    let delivery_queue_depth = IntGauge::new(
+        "issue_delivery_queue_depth",
+        "Emails waiting in the issue delivery queue.",
+    )?;
+    delivery_queue_depth.set(queue_depth);
+    sampled.register(Box::new(delivery_queue_depth))?;
+
+    let mut families = METRICS.registry.gather();
+    families.extend(sampled.gather());
+    encode(&families)
+}
+
+fn encode(families: &[MetricFamily]) -> Result<String, anyhow::Error> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(families, &mut buffer)
+        .context("Failed to encode the metrics.")?;
+    String::from_utf8(buffer).context("The encoded metrics are not valid UTF-8.")
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{encode, record_email, EmailOutcome, METRICS};
+
+    #[test]
+    fn emails_are_counted_per_issue_and_outcome() {
+        let issue_id = Uuid::new_v4();
+        record_email(issue_id, EmailOutcome::Sent);
+        record_email(issue_id, EmailOutcome::Sent);
+        record_email(issue_id, EmailOutcome::Retried);
+
+        let text = encode(&METRICS.registry.gather()).unwrap();
+        assert!(text.contains(&format!(
+            r#"newsletter_emails_total{{newsletter_issue_id="{issue_id}",outcome="sent"}} 2"#
+        )));
+        assert!(text.contains(&format!(
+            r#"newsletter_emails_total{{newsletter_issue_id="{issue_id}",outcome="retried"}} 1"#
+        )));
+        assert!(!text.contains(&format!(
+            r#"newsletter_emails_total{{newsletter_issue_id="{issue_id}",outcome="failed"}}"#
+        )));
+    }
+}
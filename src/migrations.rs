@@ -0,0 +1,109 @@
+use anyhow::Context;
+use sqlx::{
+    migrate::{Migrate, Migrator},
+    Connection, PgConnection, PgPool,
+};
+
+use crate::configuration::DatabaseSettings;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(thiserror::Error, Debug)]
+pub enum MigrationError {
+    #[error(
+        "The database schema is newer than this binary: migration {applied} has been applied \
+        but the latest one this build knows about is {latest_known}. \
+        Deploy a newer build or restore the database."
+    )]
+    SchemaTooNew { applied: i64, latest_known: i64 },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Applies the migrations embedded from `migrations/` at build time.
+///
+/// Holds a Postgres advisory lock throughout, so instances starting side by side
+/// take turns rather than racing each other.
+#[tracing::instrument(name = "Running database migrations", skip(configuration))]
+pub async fn run_migrations(configuration: &DatabaseSettings) -> Result<(), MigrationError> {
+    // a dedicated connection, so the session-level lock can't leak back into a pool
+    let mut connection = PgConnection::connect_with(&configuration.with_db())
+        .await
+        .context("Failed to connect to Postgres.")?;
+    connection
+        .lock()
+        .await
+        .context("Failed to acquire the migration lock.")?;
+
+    let outcome = check_and_migrate(&mut connection).await;
+
+    connection
+        .unlock()
+        .await
+        .context("Failed to release the migration lock.")?;
+    outcome
+}
+
+async fn check_and_migrate(connection: &mut PgConnection) -> Result<(), MigrationError> {
+    connection
+        .ensure_migrations_table()
+        .await
+        .context("Failed to create the migrations table.")?;
+    let applied = connection
+        .list_applied_migrations()
+        .await
+        .context("Failed to list applied migrations.")?;
+
+    let latest_known = latest_known_version();
+    if let Some(applied) = applied
+        .iter()
+        .map(|m| m.version)
+        .find(|v| !MIGRATOR.iter().any(|m| m.version == *v))
+    {
+        return Err(MigrationError::SchemaTooNew {
+            applied,
+            latest_known,
+        });
+    }
+
+    MIGRATOR
+        .run(connection)
+        .await
+        .context("Failed to apply migrations.")?;
+    Ok(())
+}
+
+/// Fails unless the database is at exactly the migration this build expects.
+pub async fn check_schema_version(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire a connection.")?;
+    if let Some(version) = connection
+        .dirty_version()
+        .await
+        .context("Failed to check for failed migrations.")?
+    {
+        anyhow::bail!("Migration {version} failed part way through.");
+    }
+    let applied = connection
+        .list_applied_migrations()
+        .await
+        .context("Failed to list applied migrations.")?
+        .iter()
+        .map(|m| m.version)
+        .max()
+        .unwrap_or_default();
+
+    let latest_known = latest_known_version();
+    if applied != latest_known {
+        anyhow::bail!(
+            "The database is at migration {applied}, but this build expects {latest_known}."
+        );
+    }
+    Ok(())
+}
+
+fn latest_known_version() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or_default()
+}
@@ -0,0 +1,408 @@
+use std::io::{self, Write};
+
+use opentelemetry::{
+    sdk::{
+        export::trace::SpanData,
+        trace::{EvictedQueue, Span, SpanProcessor},
+    },
+    trace::{Event, TraceResult},
+    Context, KeyValue,
+};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// What happens to personal data in our logs.
+///
+/// Fields are told apart by name: anything ending in `email`, `_name`, `username` or `_ip`
+/// (e.g. `subscriber_email`, `http.client_ip`) is personal data, so name new fields accordingly.
+/// Both the Bunyan output and the spans exported over OTLP are redacted.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Redaction {
+    /// Logged as they are, for local development.
+    Plain,
+    /// Replaced with `[redacted]`.
+    Mask,
+    /// Replaced with a short hash, so the lines about one subscriber can still be found.
+    ///
+    /// Unsalted, so common values can be guessed from it - use `mask` where that matters.
+    #[default]
+    Hash,
+}
+
+impl Redaction {
+    fn apply(&self, value: &Value) -> Value {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        Value::String(self.redact(value))
+    }
+
+    fn redact(&self, value: String) -> String {
+        match self {
+            Redaction::Plain => value,
+            Redaction::Mask => "[redacted]".into(),
+            Redaction::Hash => {
+                let digest = Sha256::digest(value.as_bytes());
+                format!("sha256:{}", hex::encode(&digest[..8]))
+            }
+        }
+    }
+
+    /// Redacts the personal data among the attributes of a span or span event.
+    fn redact_attributes<'a>(
+        &self,
+        attributes: impl Iterator<Item = &'a KeyValue>,
+    ) -> Vec<KeyValue> {
+        attributes
+            .filter(|kv| is_personal_data(kv.key.as_str()))
+            .map(|kv| KeyValue::new(kv.key.clone(), self.redact(kv.value.to_string())))
+            .collect()
+    }
+
+    /// Redacts the personal data in one line of Bunyan output.
+    ///
+    /// Lines that aren't JSON objects are passed on untouched.
+    fn redact_line(&self, line: &[u8]) -> Vec<u8> {
+        let mut record = match serde_json::from_slice::<Value>(line) {
+            Ok(Value::Object(record)) => record,
+            _ => return line.to_vec(),
+        };
+        for (key, value) in record.iter_mut() {
+            if is_personal_data(key) {
+                *value = self.apply(value);
+            }
+        }
+        serde_json::to_vec(&record).unwrap_or_else(|_| line.to_vec())
+    }
+}
+
+fn is_personal_data(field: &str) -> bool {
+    field.ends_with("email")
+        || field.ends_with("_name")
+        || field.ends_with("username")
+        || field.ends_with("_ip")
+}
+
+/// Redacts the records written to `sink`.
+pub struct RedactingMakeWriter<Sink> {
+    sink: Sink,
+    redaction: Redaction,
+}
+
+impl<Sink> RedactingMakeWriter<Sink> {
+    pub fn new(sink: Sink, redaction: Redaction) -> Self {
+        Self { sink, redaction }
+    }
+}
+
+impl<'a, Sink> MakeWriter<'a> for RedactingMakeWriter<Sink>
+where
+    Sink: MakeWriter<'a>,
+{
+    type Writer = RedactingWriter<Sink::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            inner: self.sink.make_writer(),
+            redaction: self.redaction,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+/// Holds on to what is written until a whole line is there to redact.
+pub struct RedactingWriter<W: Write> {
+    inner: W,
+    redaction: Redaction,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.redaction == Redaction::Plain {
+            return self.inner.write(buf);
+        }
+
+        self.buffer.extend_from_slice(buf);
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let mut redacted = self.redaction.redact_line(&line[..end]);
+            redacted.push(b'\n');
+            self.inner.write_all(&redacted)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for RedactingWriter<W> {
+    fn drop(&mut self) {
+        // Bunyan ends every record with a newline, so this is only ever a partial one
+        if !self.buffer.is_empty() {
+            let redacted = self.redaction.redact_line(&self.buffer);
+            let _ = self.inner.write_all(&redacted);
+        }
+    }
+}
+
+/// Redacts the spans handed to `inner`, e.g. the batch processor of the OTLP exporter.
+#[derive(Debug)]
+pub struct RedactingSpanProcessor<P> {
+    inner: P,
+    redaction: Redaction,
+}
+
+impl<P> RedactingSpanProcessor<P> {
+    pub fn new(inner: P, redaction: Redaction) -> Self {
+        Self { inner, redaction }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for RedactingSpanProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        if self.redaction != Redaction::Plain {
+            let attributes: Vec<KeyValue> = span
+                .attributes
+                .iter()
+                .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+                .collect();
+            // inserting under the same key replaces the raw value
+            for attribute in self.redaction.redact_attributes(attributes.iter()) {
+                span.attributes.insert(attribute);
+            }
+            let events: Vec<Event> = span.events.into_iter().collect();
+            let mut redacted = EvictedQueue::new(events.len() as u32);
+            redacted.extend(events.into_iter().map(|mut event| {
+                let personal = self.redaction.redact_attributes(event.attributes.iter());
+                event
+                    .attributes
+                    .retain(|kv| !is_personal_data(kv.key.as_str()));
+                event.attributes.extend(personal);
+                event
+            }));
+            span.events = redacted;
+        }
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use opentelemetry::{
+        sdk::{
+            export::trace::SpanData,
+            trace::{Span, SpanProcessor, TracerProvider},
+        },
+        trace::{TraceResult, TracerProvider as _},
+        Context,
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::{RedactingSpanProcessor, Redaction};
+    use crate::telemetry::get_subscriber;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// Stands in for the OTLP exporter, keeping the spans it is given.
+    #[derive(Clone, Debug, Default)]
+    struct Exported(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanProcessor for Exported {
+        fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            self.0.lock().unwrap().push(span);
+        }
+
+        fn force_flush(&self) -> TraceResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> TraceResult<()> {
+            Ok(())
+        }
+    }
+
+    /// A span and event shaped like the ones `subscribe` logs.
+    fn a_new_subscriber() {
+        let _span = tracing::info_span!(
+            "Adding a new subscriber",
+            subscriber_email = %"ursula_le_guin@gmail.com",
+            subscriber_name = %"Ursula",
+            remote_ip = %"203.0.113.7",
+        )
+        .entered();
+        tracing::info!(subscriber_email = %"ursula_le_guin@gmail.com", "Sent a confirmation email");
+    }
+
+    /// The Bunyan output of [`a_new_subscriber`].
+    fn log_a_new_subscriber(redaction: Redaction) -> String {
+        let captured = Captured::default();
+        let subscriber = get_subscriber(
+            "test".into(),
+            "info".into(),
+            captured.clone(),
+            None,
+            redaction,
+        );
+        tracing::subscriber::with_default(subscriber, a_new_subscriber);
+        let output = captured.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    /// The span of [`a_new_subscriber`] as it would be exported over OTLP.
+    fn export_a_new_subscriber(redaction: Redaction) -> SpanData {
+        let exported = Exported::default();
+        let provider = TracerProvider::builder()
+            .with_span_processor(RedactingSpanProcessor::new(exported.clone(), redaction))
+            .build();
+        let subscriber = get_subscriber(
+            "test".into(),
+            "info".into(),
+            io::sink,
+            Some(provider.tracer("test")),
+            redaction,
+        );
+        tracing::subscriber::with_default(subscriber, a_new_subscriber);
+        let mut spans = exported.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        spans.remove(0)
+    }
+
+    /// Every attribute of the span and its events, as `key=value`.
+    fn attributes(span: &SpanData) -> Vec<String> {
+        let span_attributes = span
+            .attributes
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"));
+        let event_attributes = span
+            .events
+            .iter()
+            .flat_map(|event| event.attributes.iter())
+            .map(|kv| format!("{}={}", kv.key, kv.value));
+        span_attributes.chain(event_attributes).collect()
+    }
+
+    #[test]
+    fn no_raw_email_shows_up_when_hashing() {
+        let output = log_a_new_subscriber(Redaction::Hash);
+        assert!(output.contains("[ADDING A NEW SUBSCRIBER - START]"));
+        assert!(!output.contains("ursula_le_guin@gmail.com"));
+        assert!(!output.contains("Ursula"));
+        assert!(!output.contains("203.0.113.7"));
+        assert!(output.contains(r#""subscriber_email":"sha256:"#));
+    }
+
+    #[test]
+    fn no_raw_email_is_exported_when_hashing() {
+        let attributes = attributes(&export_a_new_subscriber(Redaction::Hash));
+        for raw in ["ursula_le_guin@gmail.com", "Ursula", "203.0.113.7"] {
+            assert!(
+                !attributes.iter().any(|a| a.contains(raw)),
+                "{raw} was exported: {attributes:?}"
+            );
+        }
+        let emails: Vec<_> = attributes
+            .iter()
+            .filter(|a| a.starts_with("subscriber_email="))
+            .collect();
+        // one on the span, one on its event
+        assert_eq!(emails.len(), 2);
+        assert!(emails
+            .iter()
+            .all(|a| a.starts_with("subscriber_email=sha256:")));
+    }
+
+    #[test]
+    fn everything_is_exported_in_plain_mode() {
+        let attributes = attributes(&export_a_new_subscriber(Redaction::Plain));
+        assert!(attributes.contains(&"subscriber_email=ursula_le_guin@gmail.com".to_string()));
+    }
+
+    #[test]
+    fn hashes_are_the_same_for_the_same_value() {
+        let redaction = Redaction::Hash;
+        let value = serde_json::json!("ursula_le_guin@gmail.com");
+        assert_eq!(redaction.apply(&value), redaction.apply(&value));
+        assert_ne!(
+            redaction.apply(&value),
+            redaction.apply(&serde_json::json!("le_guin@gmail.com"))
+        );
+    }
+
+    #[test]
+    fn no_raw_email_shows_up_when_masking() {
+        let output = log_a_new_subscriber(Redaction::Mask);
+        assert!(!output.contains("ursula_le_guin@gmail.com"));
+        assert!(output.contains(r#""subscriber_email":"[redacted]""#));
+    }
+
+    #[test]
+    fn everything_is_kept_in_plain_mode() {
+        let output = log_a_new_subscriber(Redaction::Plain);
+        assert!(output.contains(r#""subscriber_email":"ursula_le_guin@gmail.com""#));
+    }
+
+    #[test]
+    fn the_bootstrap_admin_credential_is_left_readable() {
+        // as logged by `Application::build`, useless if either half were hashed
+        let line = br#"{"admin_login":"admin","one_time_password":"s3cret"}"#;
+        let redacted = Redaction::Hash.redact_line(line);
+        let redacted: serde_json::Value = serde_json::from_slice(&redacted).unwrap();
+        assert_eq!(redacted["admin_login"], "admin");
+        assert_eq!(redacted["one_time_password"], "s3cret");
+    }
+
+    #[test]
+    fn other_fields_are_left_alone() {
+        let line = br#"{"name":"zero2prod","msg":"Hi","newsletter_issue_id":"abc","subscriber_email":"a@b.c"}"#;
+        let redacted = Redaction::Mask.redact_line(line);
+        let redacted: serde_json::Value = serde_json::from_slice(&redacted).unwrap();
+        assert_eq!(redacted["name"], "zero2prod");
+        assert_eq!(redacted["msg"], "Hi");
+        assert_eq!(redacted["newsletter_issue_id"], "abc");
+        assert_eq!(redacted["subscriber_email"], "[redacted]");
+    }
+}
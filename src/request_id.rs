@@ -0,0 +1,150 @@
+use std::fmt::Display;
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{ContentType, HeaderName, HeaderValue, CONTENT_TYPE},
+        StatusCode,
+    },
+    Error, HttpMessage,
+};
+use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::middleware::Next;
+use askama::Template;
+use tracing::{field::display, Span};
+use tracing_actix_web::{root_span, DefaultRootSpanBuilder, RootSpanBuilder};
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Identifies a request in our logs, in responses and in the delivery tasks it creates.
+///
+/// Handlers get it with `web::ReqData<RequestId>`.
+#[derive(Clone, Debug)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The id sent by the load balancer if it looks sane, the one `TracingLogger` made up otherwise.
+    fn for_request(request: &ServiceRequest) -> Self {
+        let incoming = request
+            .headers()
+            .get(X_REQUEST_ID)
+            .and_then(|h| h.to_str().ok())
+            .filter(|id| is_acceptable(id));
+        match incoming {
+            Some(id) => Self(id.to_string()),
+            None => Self(
+                request
+                    .extensions()
+                    .get::<tracing_actix_web::RequestId>()
+                    .map(ToString::to_string)
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+}
+
+impl AsRef<str> for RequestId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// It ends up in headers, logs and pages, so only a conservative set of characters is let through.
+fn is_acceptable(id: &str) -> bool {
+    (1..=128).contains(&id.len())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '='))
+}
+
+/// The root span of `TracingLogger`, with the request id we hand out.
+pub struct RequestIdRootSpanBuilder;
+
+impl RootSpanBuilder for RequestIdRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let span = root_span!(request);
+        let request_id = RequestId::for_request(request);
+        span.record("request_id", display(&request_id));
+        request.extensions_mut().insert(request_id);
+        span
+    }
+
+    fn on_request_end<B>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+/// An error flash message naming the request, for the user to quote to support.
+pub fn error_flash(message: impl Display, request_id: &RequestId) -> FlashMessage {
+    FlashMessage::error(format!("{message} (request id: {request_id})"))
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorPage<'a> {
+    request_id: &'a str,
+}
+
+/// Sends the request id back, and swaps the body of 500s for a page that names it.
+///
+/// Must run inside `TracingLogger`, which decides the id.
+pub async fn add_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .cloned()
+        .unwrap_or_else(|| RequestId::for_request(&req));
+
+    let mut response = next.call(req).await?;
+    // checked by `is_acceptable` or made up by us
+    response.headers_mut().insert(
+        X_REQUEST_ID,
+        HeaderValue::from_str(request_id.as_ref()).unwrap(),
+    );
+    if response.status() != StatusCode::INTERNAL_SERVER_ERROR {
+        return Ok(response.map_into_left_body());
+    }
+
+    // the default body is the error message, which is for our logs rather than the user
+    let page = ErrorPage {
+        request_id: request_id.as_ref(),
+    }
+    .render()
+    .unwrap_or_else(|_| format!("Something went wrong (request id: {request_id})."));
+    let (req, mut res) = response.into_parts();
+    res.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_str(ContentType::html().0.as_ref()).unwrap(),
+    );
+    Ok(ServiceResponse::new(req, res.set_body(page).map_into_boxed_body()).map_into_right_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_acceptable;
+
+    #[test]
+    fn sane_ids_from_the_load_balancer_are_accepted() {
+        assert!(is_acceptable("4bf92f35-77b3-4da6-a3ce-929d0e0e4736"));
+        assert!(is_acceptable("Root=1-63a4c2b1-5f0c8a3e2b1d4c6e7f8a9b0c"));
+    }
+
+    #[test]
+    fn ids_that_could_inject_anything_are_rejected() {
+        assert!(!is_acceptable(""));
+        assert!(!is_acceptable("<script>alert(1)</script>"));
+        assert!(!is_acceptable("abc\r\nSet-Cookie: id=1"));
+        assert!(!is_acceptable("a b"));
+        assert!(!is_acceptable(&"a".repeat(129)));
+    }
+}
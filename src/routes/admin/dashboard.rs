@@ -1,43 +1,42 @@
-use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
 use sqlx::PgPool;
 
 use crate::{
     authentication::UserId,
-    routes::{e500, utils::get_username},
+    routes::{
+        e500,
+        utils::{collect_flash_messages, get_username, render},
+    },
+    session_state::TypedSession,
+    tracking::{get_issue_stats, IssueStats},
 };
 
-#[tracing::instrument(name = "Delivering admin dashboard", skip(user_id, pool))]
+#[derive(Template)]
+#[template(path = "admin/dashboard.html")]
+struct DashboardPage {
+    csrf_token: String,
+    flash_messages: Vec<String>,
+    username: String,
+    issue_stats: Vec<IssueStats>,
+}
+
+#[tracing::instrument(
+    name = "Delivering admin dashboard",
+    skip(user_id, pool, session, flash_messages)
+)]
 pub async fn admin_dashboard(
     user_id: web::ReqData<UserId>,
     pool: web::Data<PgPool>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
-    let username = get_username(*user_id, &pool).await.map_err(e500)?;
-
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"
-            <!DOCTYPE html>
-            <html lang="en">
-            <head>
-                <meta http-equiv="content-type" content="text/html; charset=utf-8">
-                <title>Admin dashboard</title>
-            </head>
-            <body>
-                <p>Welcome {username}!</p>
-                <p>Available actions:</p>
-                <ol>
-                    <li><a href="/admin/newsletters">Send a newsletter issue</a></li>
-                    <li><a href="/admin/password">Change password</a></li>
-                    <li>
-                        <form name="logoutForm" action="/admin/logout" method="post">
-                            <input type="submit" value="logout">
-                        </form>
-                    </li>
-                </ol>
-            </body>
-            </html>
-            "#
-        )))
+    render(&DashboardPage {
+        csrf_token: session.csrf_token().map_err(e500)?,
+        flash_messages: collect_flash_messages(&flash_messages),
+        username: get_username(*user_id, &pool).await.map_err(e500)?,
+        issue_stats: get_issue_stats(&pool).await.map_err(e500)?,
+    })
 }
@@ -1,17 +1,26 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
 
 use crate::{
+    authentication::{revoke_session, UserId},
     routes::utils::{e500, see_other},
     session_state::TypedSession,
 };
 
-#[tracing::instrument(name = "Logging out", skip(session))]
-pub async fn logout(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
-    if session.get_user_id().map_err(e500)?.is_some() {
-        session.logout();
-        FlashMessage::info("You have successfully logged out.").send();
+#[tracing::instrument(name = "Logging out", skip(user_id, pool, session))]
+pub async fn logout(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(session_id) = session.get_session_id().map_err(e500)? {
+        revoke_session(**user_id, session_id, &pool)
+            .await
+            .map_err(e500)?;
     }
+    session.logout();
+    FlashMessage::info("You have successfully logged out.").send();
 
     Ok(see_other("/login"))
 }
@@ -2,8 +2,10 @@ mod dashboard;
 mod logout;
 mod newsletters;
 mod password;
+mod sessions;
 
 pub use dashboard::admin_dashboard;
 pub use logout::*;
 pub use newsletters::*;
 pub use password::*;
+pub use sessions::*;
@@ -1,46 +1,41 @@
-use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use askama::Template;
+use uuid::Uuid;
 
-use crate::authentication::UserId;
+use crate::{
+    authentication::UserId,
+    routes::{
+        e500,
+        utils::{collect_flash_messages, render},
+    },
+    session_state::TypedSession,
+    tracking::IssueTracker,
+};
 
-#[tracing::instrument(name = "Delivering publish newsletter form", skip(flash_messages))]
+#[derive(Template)]
+#[template(path = "admin/newsletters.html")]
+struct PublishNewsletterPage {
+    csrf_token: String,
+    flash_messages: Vec<String>,
+    idempotency_key: Uuid,
+    tracking_enabled: bool,
+}
+
+#[tracing::instrument(
+    name = "Delivering publish newsletter form",
+    skip(flash_messages, issue_tracker, session)
+)]
 pub async fn publish_newsletter_form(
     _: web::ReqData<UserId>,
     flash_messages: IncomingFlashMessages,
+    issue_tracker: web::Data<IssueTracker>,
+    session: TypedSession,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let mut msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
-
-    let idempotency_key = uuid::Uuid::new_v4();
-
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-        <html lang="en">
-          <head>
-            <meta http-equiv="content-type" content="text/html; charset=utf-8" />
-            <title>Post Newsletter</title>
-          </head>
-          <body>
-            {msg_html}
-            <form action="/admin/newsletters" method="post">
-              <label>Title
-                <input type="text" placeholder="New Newsletter" name="title"/>
-              </label>
-              <label>Text Content
-                <input type="text" placeholder="Content" name="text_content"/>
-              </label>
-              <label>HTML Content
-                <input type="text" placeholder="Content" name="html_content"/>
-              </label>
-              <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
-              <button type="submit">Post</button>
-            </form>
-          </body>
-        </html>"#
-        )))
+    render(&PublishNewsletterPage {
+        csrf_token: session.csrf_token().map_err(e500)?,
+        flash_messages: collect_flash_messages(&flash_messages),
+        idempotency_key: Uuid::new_v4(),
+        tracking_enabled: issue_tracker.is_enabled(),
+    })
 }
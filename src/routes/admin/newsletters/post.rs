@@ -7,7 +7,10 @@ use uuid::Uuid;
 use crate::{
     authentication::UserId,
     idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    request_id::RequestId,
     routes::{e500, see_other, utils::e400},
+    telemetry::current_traceparent,
+    tracking::IssueTracker,
 };
 
 #[derive(serde::Deserialize)]
@@ -16,12 +19,16 @@ pub struct FormData {
     html_content: String,
     text_content: String,
     idempotency_key: String,
+    #[serde(default)]
+    tracking_enabled: bool,
 }
 
 pub async fn publish_newsletter(
     user_id: web::ReqData<UserId>,
+    request_id: web::ReqData<RequestId>,
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    issue_tracker: web::Data<IssueTracker>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = *(user_id.into_inner());
 
@@ -30,6 +37,7 @@ pub async fn publish_newsletter(
         html_content,
         text_content,
         idempotency_key,
+        tracking_enabled,
     } = form.0;
 
     let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
@@ -45,12 +53,18 @@ pub async fn publish_newsletter(
         }
     };
 
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
-        .await
-        .context("Failed to store newsletter issue details")
-        .map_err(e500)?;
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &text_content,
+        &html_content,
+        tracking_enabled && issue_tracker.is_enabled(),
+    )
+    .await
+    .context("Failed to store newsletter issue details")
+    .map_err(e500)?;
 
-    enqueue_delivery_tasks(&mut transaction, issue_id)
+    enqueue_delivery_tasks(&mut transaction, issue_id, &request_id)
         .await
         .context("Failed to enqueue delivery tasks")
         .map_err(e500)?;
@@ -74,6 +88,7 @@ async fn insert_newsletter_issue(
     title: &str,
     text_content: &str,
     html_content: &str,
+    tracking_enabled: bool,
 ) -> Result<Uuid, sqlx::Error> {
     let newsletter_issue_id = Uuid::new_v4();
 
@@ -84,14 +99,16 @@ async fn insert_newsletter_issue(
              title,
              text_content,
              html_content,
-             published_at
+             published_at,
+             tracking_enabled
              )
-         VALUES ($1, $2, $3, $4, now())
+         VALUES ($1, $2, $3, $4, now(), $5)
          "#,
         newsletter_issue_id,
         title,
         text_content,
-        html_content
+        html_content,
+        tracking_enabled
     )
     .execute(transaction)
     .await?;
@@ -103,22 +120,32 @@ async fn insert_newsletter_issue(
 async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
+    request_id: &RequestId,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"
         INSERT INTO issue_delivery_queue (
             newsletter_issue_id,
-            subscriber_email
+            subscriber_email,
+            traceparent,
+            request_id
             )
-        SELECT $1, email
+        SELECT $1, email, $2, $3
         FROM
             subscriptions
         WHERE
             status = 'confirmed'
          "#,
         newsletter_issue_id,
+        current_traceparent(),
+        request_id.as_ref(),
     )
-    .execute(transaction)
+    .execute(&mut *transaction)
     .await?;
+
+    // delivered on commit, waking up idle workers (see `NEW_TASKS_CHANNEL`)
+    sqlx::query!("NOTIFY issue_delivery_queue")
+        .execute(transaction)
+        .await?;
     Ok(())
 }
@@ -1,46 +1,34 @@
-use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use askama::Template;
 
-use crate::authentication::UserId;
+use crate::{
+    authentication::UserId,
+    routes::{
+        e500,
+        utils::{collect_flash_messages, render},
+    },
+    session_state::TypedSession,
+};
 
-#[tracing::instrument(name = "Delivering change password form", skip(flash_messages))]
+#[derive(Template)]
+#[template(path = "admin/password.html")]
+struct ChangePasswordPage {
+    csrf_token: String,
+    flash_messages: Vec<String>,
+}
+
+#[tracing::instrument(
+    name = "Delivering change password form",
+    skip(flash_messages, session)
+)]
 pub async fn change_password_form(
     _: web::ReqData<UserId>,
     flash_messages: IncomingFlashMessages,
+    session: TypedSession,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let mut msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
-
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-        <html lang="en">
-          <head>
-            <meta http-equiv="content-type" content="text/html; charset=utf-8" />
-            <title>Change Password</title>
-          </head>
-          <body>
-              {msg_html}
-            <form action="/admin/password" method="post">
-              <label>Current Password
-                <input type="password" placeholder="Enter Current Password" name="current_password"/>
-              </label>
-              <br />
-              <label>New Password
-                <input type="password" placeholder="Enter New Password" name="new_password"/>
-              </label>
-              <br />
-              <label>Confirm Password
-                <input type="password" placeholder="Enter Password Again" name="new_password_check"/>
-              </label>
-              <br />
-              <button type="submit">Change Password</button>
-            </form>
-          </body>
-        </html>"#
-        )))
+    render(&ChangePasswordPage {
+        csrf_token: session.csrf_token().map_err(e500)?,
+        flash_messages: collect_flash_messages(&flash_messages),
+    })
 }
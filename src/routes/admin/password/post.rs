@@ -4,8 +4,10 @@ use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
 
 use crate::{
-    authentication::{validate_credential, Credential, UserId},
+    authentication::{revoke_sessions, validate_credential, Credential, UserId},
+    request_id::{error_flash, RequestId},
     routes::utils::{e500, get_username, see_other},
+    session_state::TypedSession,
 };
 
 #[derive(serde::Deserialize)]
@@ -17,14 +19,26 @@ pub struct FormData {
 
 pub async fn change_password(
     user_id: web::ReqData<UserId>,
+    request_id: web::ReqData<RequestId>,
     pool: web::Data<PgPool>,
     form: web::Form<FormData>,
+    session: TypedSession,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
 
     if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
-        FlashMessage::error(
+        error_flash(
             "You entered two different new passwords - the field values must match.",
+            &request_id,
+        )
+        .send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    if form.new_password.expose_secret() == form.current_password.expose_secret() {
+        error_flash(
+            "Your new password must be different from the current one.",
+            &request_id,
         )
         .send();
         return Ok(see_other("/admin/password"));
@@ -40,7 +54,7 @@ pub async fn change_password(
     if let Err(e) = validate_credential(credential, &pool).await {
         return match e {
             crate::authentication::AuthError::InvalidCredentials(_) => {
-                FlashMessage::error("You entered an invalid password.").send();
+                error_flash("You entered an invalid password.", &request_id).send();
                 Ok(see_other("/admin/password"))
             }
             crate::authentication::AuthError::UnexpectedError(_) => Err(e500(e)),
@@ -50,6 +64,11 @@ pub async fn change_password(
     crate::authentication::change_password(*user_id, form.0.new_password, &pool)
         .await
         .map_err(e500)?;
+    // anyone else holding a session may have got in with the old password
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    revoke_sessions(*user_id, current_session_id, &pool)
+        .await
+        .map_err(e500)?;
     FlashMessage::info("Your password has been changed.").send();
     Ok(see_other("/admin/password"))
 }
@@ -0,0 +1,86 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{list_sessions, revoke_sessions, SessionRecord, UserId},
+    configuration::SessionSettings,
+    request_id::{error_flash, RequestId},
+    routes::utils::{collect_flash_messages, e500, render, see_other},
+    session_state::TypedSession,
+};
+
+#[derive(Template)]
+#[template(path = "admin/sessions.html")]
+struct SessionsPage {
+    csrf_token: String,
+    flash_messages: Vec<String>,
+    sessions: Vec<SessionRecord>,
+    current_session_id: Option<Uuid>,
+}
+
+impl SessionsPage {
+    fn is_current(&self, session: &SessionRecord) -> bool {
+        self.current_session_id == Some(session.session_id)
+    }
+}
+
+#[tracing::instrument(
+    name = "Delivering sessions page",
+    skip(user_id, pool, session, session_settings, flash_messages)
+)]
+pub async fn sessions_page(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    session_settings: web::Data<SessionSettings>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&SessionsPage {
+        csrf_token: session.csrf_token().map_err(e500)?,
+        flash_messages: collect_flash_messages(&flash_messages),
+        sessions: list_sessions(**user_id, &session_settings, &pool)
+            .await
+            .map_err(e500)?,
+        current_session_id: session.get_session_id().map_err(e500)?,
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct RevokeSessionFormData {
+    session_id: Uuid,
+}
+
+#[tracing::instrument(name = "Revoking a session", skip(user_id, request_id, pool, form))]
+pub async fn revoke_session(
+    user_id: web::ReqData<UserId>,
+    request_id: web::ReqData<RequestId>,
+    pool: web::Data<PgPool>,
+    form: web::Form<RevokeSessionFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if crate::authentication::revoke_session(**user_id, form.session_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("The session has been revoked.").send();
+    } else {
+        error_flash("The session had already ended.", &request_id).send();
+    }
+    Ok(see_other("/admin/sessions"))
+}
+
+#[tracing::instrument(name = "Logging out everywhere", skip(user_id, pool, session))]
+pub async fn logout_everywhere(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    revoke_sessions(**user_id, None, &pool)
+        .await
+        .map_err(e500)?;
+    session.logout();
+    FlashMessage::info("You have been logged out of all sessions.").send();
+    Ok(see_other("/login"))
+}
@@ -1,5 +1,111 @@
-use actix_web::HttpResponse;
+use std::{collections::BTreeMap, future::Future, time::Duration};
 
+use actix_web::{http::StatusCode, web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use tokio::time::Instant;
+
+use crate::migrations::check_schema_version;
+
+/// How long each dependency gets to answer before it counts as down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness: the process is up and serving requests.
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+#[derive(serde::Serialize)]
+struct Readiness {
+    status: &'static str,
+    checks: BTreeMap<&'static str, CheckReport>,
+}
+
+#[derive(serde::Serialize)]
+struct CheckReport {
+    status: &'static str,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl CheckReport {
+    fn is_up(&self) -> bool {
+        self.status == "up"
+    }
+}
+
+/// Readiness: every dependency we need to handle requests is reachable.
+pub async fn readiness(pool: web::Data<PgPool>, redis: web::Data<redis::Client>) -> HttpResponse {
+    let (postgres, redis, migrations) = tokio::join!(
+        run_check(check_postgres(&pool)),
+        run_check(check_redis(&redis)),
+        run_check(check_schema_version(&pool)),
+    );
+    let checks = BTreeMap::from([
+        ("postgres", postgres),
+        ("redis", redis),
+        ("migrations", migrations),
+    ]);
+
+    let failed: Vec<_> = checks
+        .iter()
+        .filter(|(_, check)| !check.is_up())
+        .map(|(name, _)| *name)
+        .collect();
+    let ready = failed.is_empty();
+    if !ready {
+        tracing::warn!(?failed, "Not ready to serve requests");
+    }
+    let (status, code) = if ready {
+        ("ready", StatusCode::OK)
+    } else {
+        ("unavailable", StatusCode::SERVICE_UNAVAILABLE)
+    };
+    HttpResponse::build(code).json(Readiness { status, checks })
+}
+
+async fn run_check(check: impl Future<Output = Result<(), anyhow::Error>>) -> CheckReport {
+    let start = Instant::now();
+    let outcome = tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out.")));
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    match outcome {
+        Ok(()) => CheckReport {
+            status: "up",
+            latency_ms,
+            error: None,
+        },
+        Err(e) => CheckReport {
+            status: "down",
+            latency_ms,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+async fn check_postgres(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire a connection.")?;
+    sqlx::query("SELECT 1")
+        .execute(&mut connection)
+        .await
+        .context("Failed to run a query.")?;
+    Ok(())
+}
+
+async fn check_redis(client: &redis::Client) -> Result<(), anyhow::Error> {
+    let mut connection = client
+        .get_async_connection()
+        .await
+        .context("Failed to connect.")?;
+    redis::cmd("PING")
+        .query_async::<_, String>(&mut connection)
+        .await
+        .context("Failed to ping.")?;
+    Ok(())
+}
@@ -1,15 +1,65 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
+use askama::Template;
 
 use crate::{
-    routes::{e500, see_other},
+    anti_abuse::{ChallengeVerifier, FormTokens},
+    i18n::{LanguageIdentifier, Localizer},
+    routes::{accept_language, e500, render, see_other},
+    security_headers::CspNonce,
     session_state::TypedSession,
 };
 
-pub async fn home(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
+#[derive(Template)]
+#[template(path = "home.html")]
+struct HomePage<'a> {
+    localizer: &'a Localizer,
+    locale: LanguageIdentifier,
+    locale_options: Vec<LocaleOption>,
+    form_token: String,
+    /// Rendered by the challenge verifier, so it is not escaped.
+    challenge_html: String,
+}
+
+struct LocaleOption {
+    value: LanguageIdentifier,
+    name: String,
+    selected: bool,
+}
+
+impl HomePage<'_> {
+    fn t(&self, id: &str) -> String {
+        self.localizer.format(&self.locale, id, None)
+    }
+}
+
+pub async fn home(
+    request: HttpRequest,
+    session: TypedSession,
+    localizer: web::Data<Localizer>,
+    form_tokens: web::Data<FormTokens>,
+    challenge_verifier: web::Data<dyn ChallengeVerifier>,
+    csp_nonce: web::ReqData<CspNonce>,
+) -> Result<HttpResponse, actix_web::Error> {
     match session.get_user_id().map_err(e500)? {
         Some(_) => Ok(see_other("/admin/dashboard")),
-        None => Ok(HttpResponse::Ok()
-            .content_type(ContentType::html())
-            .body(include_str!("home.html"))),
+        None => {
+            let locale = localizer.negotiate(accept_language(&request));
+            let locale_options = localizer
+                .available_locales()
+                .iter()
+                .map(|l| LocaleOption {
+                    value: l.clone(),
+                    name: localizer.format(l, "language-name", None),
+                    selected: l == &locale,
+                })
+                .collect();
+            render(&HomePage {
+                localizer: &localizer,
+                locale,
+                locale_options,
+                form_token: form_tokens.issue(),
+                challenge_html: challenge_verifier.form_html(csp_nonce.as_ref()),
+            })
+        }
     }
 }
@@ -1,37 +1,39 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use askama::Template;
+
+use crate::routes::utils::{collect_flash_messages, render};
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginPage {
+    flash_messages: Vec<String>,
+}
 
 #[tracing::instrument(name = "Delivering login form", skip(flash_messages))]
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
-    let mut msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
+pub async fn login_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&LoginPage {
+        flash_messages: collect_flash_messages(&flash_messages),
+    })
+}
 
-    HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-        <html lang="en">
-          <head>
-            <meta http-equiv="content-type" content="text/html; charset=utf-8" />
-            <title>Home</title>
-          </head>
-          <body>
-            {msg_html}
-            <form action="/login" method="post">
-              <label>
-                Username:
-                <input type="text" placeholder="Enter Username" name="username"/>
-              </label>
-              <label>
-                Password:
-                <input type="password" placeholder="Enter Password" name="password"/>
-              </label>
-              <button type="submit">Login</button>
-            </form>
-          </body>
-        </html>"#
-        ))
+#[cfg(test)]
+mod tests {
+    use askama::Template;
+
+    use super::LoginPage;
+
+    #[test]
+    fn flash_messages_are_escaped() {
+        let html = LoginPage {
+            flash_messages: vec!["<script>alert(1)</script>".into()],
+        }
+        .render()
+        .unwrap();
+
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("<p><i>&lt;script&gt;alert(1)&lt;/script&gt;</i></p>"));
+    }
 }
@@ -1,11 +1,13 @@
-use actix_web::{error::InternalError, web, HttpResponse};
+use actix_web::{error::InternalError, web, HttpRequest, HttpResponse};
 
-use actix_web_flash_messages::FlashMessage;
 use secrecy::Secret;
 use sqlx::PgPool;
 
 use crate::{
-    authentication::{validate_credential, Credential},
+    authentication::{record_session, validate_credential, Credential, SessionClient},
+    client_ip::TrustedProxies,
+    configuration::SessionSettings,
+    request_id::{error_flash, RequestId},
     routes::utils::{error_chain_fmt, see_other},
     session_state::TypedSession,
 };
@@ -30,11 +32,26 @@ impl std::fmt::Debug for LoginError {
     }
 }
 
-#[tracing::instrument(name = "Processing login request", skip(form, pool, session))]
+#[tracing::instrument(
+    name = "Processing login request",
+    skip(
+        request,
+        request_id,
+        form,
+        pool,
+        session,
+        session_settings,
+        trusted_proxies
+    )
+)]
 pub async fn login(
+    request: HttpRequest,
+    request_id: web::ReqData<RequestId>,
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
     session: TypedSession,
+    session_settings: web::Data<SessionSettings>,
+    trusted_proxies: web::Data<TrustedProxies>,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credential = Credential {
         username: form.0.username,
@@ -43,12 +60,23 @@ pub async fn login(
 
     match validate_credential(credential, &pool).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+            let session_id = record_session(
+                user_id,
+                &SessionClient::from_request(&request, &trusted_proxies),
+                &session_settings,
+                &pool,
+            )
+            .await
+            .map_err(|e| login_redirect(LoginError::UnexpectedError(e), &request_id))?;
 
             session.renew();
             session
                 .insert_user(user_id)
-                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                .and_then(|_| session.insert_session_id(session_id))
+                .and_then(|_| session.rotate_csrf_token())
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e), &request_id))?;
 
             Ok(see_other("/admin/dashboard"))
         }
@@ -62,13 +90,13 @@ pub async fn login(
                 }
             };
 
-            Err(login_redirect(e))
+            Err(login_redirect(e, &request_id))
         }
     }
 }
 
-fn login_redirect(e: LoginError) -> InternalError<LoginError> {
-    FlashMessage::error(e.to_string()).send();
+fn login_redirect(e: LoginError, request_id: &RequestId) -> InternalError<LoginError> {
+    error_flash(&e, request_id).send();
 
     InternalError::from_response(e, see_other("/login"))
 }
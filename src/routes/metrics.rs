@@ -0,0 +1,11 @@
+use actix_web::{web, HttpResponse};
+use prometheus::TEXT_FORMAT;
+use sqlx::PgPool;
+
+use crate::routes::e500;
+
+/// Scraped by Prometheus.
+pub async fn metrics(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let body = crate::metrics::gather(&pool).await.map_err(e500)?;
+    Ok(HttpResponse::Ok().content_type(TEXT_FORMAT).body(body))
+}
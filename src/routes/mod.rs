@@ -2,12 +2,16 @@ mod admin;
 mod health_check;
 mod home;
 mod login;
+mod metrics;
 mod subscriptions;
+mod tracking;
 mod utils;
 
 pub use admin::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
+pub use metrics::*;
 pub use subscriptions::*;
-pub use utils::{e500, see_other};
+pub use tracking::*;
+pub use utils::{accept_language, e403, e500, render, see_other};
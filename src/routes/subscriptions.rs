@@ -1,5 +1,6 @@
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
+use askama::Template;
 use chrono::Utc;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use reqwest::StatusCode;
@@ -7,8 +8,13 @@ use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    anti_abuse::{check_subscription_rate, ChallengeVerifier, FormTokens, RateLimit},
+    client_ip::TrustedProxies,
+    configuration::SubscriptionSettings,
+    domain::{EmailValidator, NewSubscriber, SubscriberEmail, SubscriberName, GMAIL_DOMAINS},
     email_client::EmailClient,
+    i18n::{FluentArgs, LanguageIdentifier, Localizer},
+    routes::accept_language,
     startup::ApplicationBaseUrl,
 };
 
@@ -16,6 +22,10 @@ use crate::{
 pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
+    #[error("The subscription request was rejected.")]
+    RejectedError(#[source] anyhow::Error),
+    #[error("Too many subscription attempts ({0} limit), try again later.")]
+    TooManyAttemptsError(RateLimit),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -30,6 +40,8 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> reqwest::StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::RejectedError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::TooManyAttemptsError(_) => StatusCode::TOO_MANY_REQUESTS,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -58,10 +70,20 @@ impl ResponseError for ConfirmSubscriberError {
     }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Clone)]
 pub(crate) struct FormData {
     pub email: String,
     pub name: String,
+    /// Honeypot - hidden from humans, so only bots fill it in.
+    #[serde(default)]
+    pub website: String,
+    #[serde(default)]
+    pub form_token: String,
+    #[serde(default)]
+    pub challenge_response: String,
+    /// Overrides the language negotiated from `Accept-Language`.
+    #[serde(default)]
+    pub locale: String,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -79,17 +101,30 @@ pub struct Parameters {
     pub subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirming subscribtion", skip(parameters, pool), fields())]
+#[derive(Template)]
+#[template(path = "subscription_confirmed.html")]
+struct SubscriptionConfirmedPage {
+    locale: LanguageIdentifier,
+    title: String,
+    body: String,
+}
+
+#[tracing::instrument(
+    name = "Confirming subscribtion",
+    skip(parameters, pool, localizer),
+    fields()
+)]
 pub(crate) async fn confirm_subscription(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
+    localizer: web::Data<Localizer>,
 ) -> Result<HttpResponse, ConfirmSubscriberError> {
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to open database transaction.")?;
 
-    let subscriber_id =
+    let (subscriber_id, locale) =
         get_subscriber_id_from_token(&parameters.subscription_token, &mut transaction)
             .await
             .context("Failed to query subscriber ID from subscription token.")?
@@ -104,33 +139,121 @@ pub(crate) async fn confirm_subscription(
         .await
         .context("Failed to commit SQL transaction to confirm new subscriber.")?;
 
-    Ok(HttpResponse::Ok().finish())
+    let locale = localizer.negotiate(&locale);
+    let page = SubscriptionConfirmedPage {
+        title: localizer.format(&locale, "confirmed-title", None),
+        body: localizer.format(&locale, "confirmed-body", None),
+        locale,
+    }
+    .render()
+    .context("Failed to render the confirmation page.")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page))
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(
+        request,
+        form,
+        pool,
+        redis,
+        email_client,
+        email_validator,
+        localizer,
+        base_url,
+        trusted_proxies,
+        form_tokens,
+        challenge_verifier,
+        settings
+    ),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
     )
 )]
 pub(crate) async fn subscribe(
+    request: HttpRequest,
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    redis: web::Data<redis::Client>,
     email_client: web::Data<EmailClient>,
+    email_validator: web::Data<EmailValidator>,
+    localizer: web::Data<Localizer>,
     base_url: web::Data<ApplicationBaseUrl>,
+    trusted_proxies: web::Data<TrustedProxies>,
+    form_tokens: web::Data<FormTokens>,
+    challenge_verifier: web::Data<dyn ChallengeVerifier>,
+    settings: web::Data<SubscriptionSettings>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let remote_ip = trusted_proxies.client_ip(&request);
+    let new_subscriber: NewSubscriber = form
+        .0
+        .clone()
+        .try_into()
+        .map_err(SubscribeError::ValidationError)?;
+    let new_subscriber = NewSubscriber {
+        email: email_validator.canonicalize(new_subscriber.email),
+        ..new_subscriber
+    };
+
+    check_abuse(
+        &form,
+        &new_subscriber.email,
+        &remote_ip,
+        &pool,
+        &redis,
+        &form_tokens,
+        challenge_verifier.as_ref(),
+        &settings,
+    )
+    .await
+    .map_err(|e| {
+        if !matches!(e, SubscribeError::UnexpectedError(_)) {
+            tracing::warn!(
+                remote_ip = %remote_ip,
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Rejected a subscription attempt."
+            );
+        }
+        e
+    })?;
+
+    let locale = localizer.negotiate(if form.locale.is_empty() {
+        accept_language(&request)
+    } else {
+        &form.locale
+    });
+    let new_subscriber = NewSubscriber {
+        email: email_validator
+            .validate(new_subscriber.email)
+            .await
+            .map_err(SubscribeError::ValidationError)?,
+        ..new_subscriber
+    };
 
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to open database transaction.")?;
 
-    let subscriber_id = insert_subscriber(&new_subscriber, &mut transaction)
+    let subscriber_id = match insert_subscriber(&new_subscriber, &locale, &mut transaction)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to insert new subscriber in the database.")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        // the address is already known, possibly typed with a different case
+        None => match get_existing_subscriber(&new_subscriber.email, &mut transaction)
+            .await
+            .context("Failed to look up an existing subscriber in the database.")?
+        {
+            (_, status) if status == "confirmed" => return Ok(HttpResponse::Ok().finish()),
+            (subscriber_id, _) => subscriber_id,
+        },
+    };
 
     let subscription_token = generate_subscription_token();
 
@@ -145,6 +268,8 @@ pub(crate) async fn subscribe(
 
     send_confirmation_email(
         &email_client,
+        &localizer,
+        &locale,
         new_subscriber,
         &base_url.0,
         &subscription_token,
@@ -155,12 +280,71 @@ pub(crate) async fn subscribe(
     Ok(HttpResponse::Ok().finish())
 }
 
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Screening subscription attempt",
+    skip(form, email, pool, redis, form_tokens, challenge_verifier, settings)
+)]
+async fn check_abuse(
+    form: &FormData,
+    email: &SubscriberEmail,
+    remote_ip: &str,
+    pool: &PgPool,
+    redis: &redis::Client,
+    form_tokens: &FormTokens,
+    challenge_verifier: &dyn ChallengeVerifier,
+    settings: &SubscriptionSettings,
+) -> Result<(), SubscribeError> {
+    if !form.website.is_empty() {
+        return Err(SubscribeError::RejectedError(anyhow::anyhow!(
+            "The honeypot field was filled in."
+        )));
+    }
+
+    form_tokens
+        .check(&form.form_token)
+        .map_err(SubscribeError::RejectedError)?;
+
+    challenge_verifier
+        .verify(&form.form_token, &form.challenge_response, Some(remote_ip))
+        .await
+        .map_err(SubscribeError::RejectedError)?;
+
+    let unused = form_tokens
+        .spend(&form.form_token, redis)
+        .await
+        .context("Failed to record the form token as used.")?;
+    if !unused {
+        return Err(SubscribeError::RejectedError(anyhow::anyhow!(
+            "The form token has already been used."
+        )));
+    }
+
+    let exceeded = check_subscription_rate(
+        pool,
+        remote_ip,
+        email.canonical(),
+        settings.rate_limit_window(),
+        settings.max_attempts_per_ip,
+        settings.max_attempts_per_email,
+    )
+    .await
+    .context("Failed to check subscription rate limits.")?;
+
+    match exceeded {
+        Some(limit) => Err(SubscribeError::TooManyAttemptsError(limit)),
+        None => Ok(()),
+    }
+}
+
 #[tracing::instrument(
     name = "Sending confirmation email to new subscriber",
-    skip(email_client, new_subscriber)
+    skip(email_client, localizer, new_subscriber)
 )]
 async fn send_confirmation_email(
     email_client: &EmailClient,
+    localizer: &Localizer,
+    locale: &LanguageIdentifier,
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
@@ -169,14 +353,14 @@ async fn send_confirmation_email(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token,
     );
-    let html_body = format!("Welcome to our newsletter!<br />Click <a href=\"{}\">here</a> to confirm your subscription.", confirmation_link);
-    let text_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
+    let mut args = FluentArgs::new();
+    args.set("link", confirmation_link);
+    let subject = localizer.format(locale, "confirmation-email-subject", None);
+    let html_body = localizer.format(locale, "confirmation-email-html", Some(&args));
+    let text_body = localizer.format(locale, "confirmation-email-text", Some(&args));
 
     email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &text_body)
+        .send_email(&new_subscriber.email, &subject, &html_body, &text_body)
         .await
 }
 
@@ -186,23 +370,114 @@ async fn send_confirmation_email(
 )]
 async fn insert_subscriber(
     new_subscriber: &NewSubscriber,
+    locale: &LanguageIdentifier,
     transaction: &mut Transaction<'_, Postgres>,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let r = sqlx::query!(
         r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-    VALUES ($1, $2, $3, $4, 'pending_confirmation')
+    INSERT INTO subscriptions (id, email, canonical_email, name, subscribed_at, status, locale)
+    VALUES ($1, $2, $3, $4, $5, 'pending_confirmation', $6)
+    ON CONFLICT (canonical_email) DO NOTHING
+    RETURNING id
     "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
+        new_subscriber.email.canonical(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        locale.to_string()
     )
-    .execute(transaction)
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(r.map(|r| r.id))
+}
+
+/// Brings `canonical_email` in line with `email_validator`, e.g. after `canonicalize_gmail` was
+/// switched on or off. Otherwise a subscriber stored under the old form is neither found by the
+/// new one nor can be inserted again, as `email` is unique too.
+///
+/// A subscriber whose new form already belongs to someone else keeps the old one.
+#[tracing::instrument(
+    name = "Updating the canonical form of subscriber emails",
+    skip(pool, email_validator)
+)]
+pub async fn backfill_canonical_emails(
+    pool: &PgPool,
+    email_validator: &EmailValidator,
+) -> Result<(), anyhow::Error> {
+    // the only addresses whose canonical form depends on the settings
+    let subscribers = sqlx::query!(
+        r#"
+        SELECT id, email, canonical_email
+        FROM subscriptions
+        WHERE split_part(email, '@', 2) = ANY($1)
+        "#,
+        &GMAIL_DOMAINS[..] as &[&str],
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the subscribers to canonicalize.")?;
+
+    for subscriber in subscribers {
+        let email = match SubscriberEmail::parse(subscriber.email) {
+            Ok(email) => email_validator.canonicalize(email),
+            Err(e) => {
+                tracing::warn!(
+                    subscriber_id = %subscriber.id,
+                    error.message = %e,
+                    "Skipping a stored subscriber email that is no longer valid."
+                );
+                continue;
+            }
+        };
+        if email.canonical() == subscriber.canonical_email {
+            continue;
+        }
+        let updated = sqlx::query!(
+            r#"
+            UPDATE subscriptions
+            SET canonical_email = $1
+            WHERE
+                id = $2 AND
+                NOT EXISTS (SELECT 1 FROM subscriptions WHERE canonical_email = $1)
+            "#,
+            email.canonical(),
+            subscriber.id,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to update the canonical form of a subscriber email.")?;
+        if updated.rows_affected() == 0 {
+            tracing::warn!(
+                subscriber_id = %subscriber.id,
+                "Kept the old canonical form of a subscriber email, the new one is taken."
+            );
+        }
+    }
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Getting an existing subscriber from the database",
+    skip(email, transaction)
+)]
+async fn get_existing_subscriber(
+    email: &SubscriberEmail,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(Uuid, String), sqlx::Error> {
+    let r = sqlx::query!(
+        r#"
+    SELECT id, status
+    FROM subscriptions
+    WHERE canonical_email = $1
+    "#,
+        email.canonical(),
+    )
+    .fetch_one(transaction)
     .await?;
 
-    Ok(subscriber_id)
+    Ok((r.id, r.status))
 }
 
 #[tracing::instrument(
@@ -235,17 +510,19 @@ async fn store_token(
 async fn get_subscriber_id_from_token(
     subscription_token: &str,
     transaction: &mut Transaction<'_, Postgres>,
-) -> Result<Option<Uuid>, sqlx::Error> {
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
     let result = sqlx::query!(
         r#"
-        SELECT subscriber_id from subscription_tokens
-        WHERE subscription_token = $1
+        SELECT t.subscriber_id, s.locale
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_token = $1
         "#,
         subscription_token
     )
     .fetch_optional(transaction)
     .await?;
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(result.map(|r| (r.subscriber_id, r.locale)))
 }
 
 #[tracing::instrument(name = "Confirming subscriber", skip(subscriber_id, transaction))]
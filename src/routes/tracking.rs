@@ -0,0 +1,117 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    routes::{e500, see_other, utils::e400},
+    tracking::{extract_links, record_click, record_open, IssueTracker},
+};
+
+// a transparent 1x1 GIF
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(serde::Deserialize)]
+pub struct OpenParameters {
+    subscriber_id: Uuid,
+    tag: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ClickParameters {
+    subscriber_id: Uuid,
+    url: String,
+    tag: String,
+}
+
+#[tracing::instrument(name = "Tracking issue open", skip(parameters, pool, issue_tracker))]
+pub async fn track_open(
+    newsletter_issue_id: web::Path<Uuid>,
+    parameters: web::Query<OpenParameters>,
+    pool: web::Data<PgPool>,
+    issue_tracker: web::Data<IssueTracker>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = newsletter_issue_id.into_inner();
+
+    issue_tracker
+        .verify_open(issue_id, parameters.subscriber_id, &parameters.tag)
+        .map_err(e400)?;
+
+    if issue_tracker.is_enabled() {
+        if let Err(e) = record_open(&pool, issue_id, parameters.subscriber_id).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to record an issue open."
+            )
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(("Cache-Control", "no-store"))
+        .body(PIXEL))
+}
+
+#[tracing::instrument(name = "Tracking issue click", skip(parameters, pool, issue_tracker))]
+pub async fn track_click(
+    newsletter_issue_id: web::Path<Uuid>,
+    parameters: web::Query<ClickParameters>,
+    pool: web::Data<PgPool>,
+    issue_tracker: web::Data<IssueTracker>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = newsletter_issue_id.into_inner();
+    let ClickParameters {
+        subscriber_id,
+        url,
+        tag,
+    } = parameters.into_inner();
+
+    issue_tracker
+        .verify_click(issue_id, subscriber_id, &url, &tag)
+        .map_err(e400)?;
+
+    // a valid tag proves we signed the link, this proves the issue still says so
+    let html_content = get_issue_html_content(&pool, issue_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e400("Unknown newsletter issue."))?;
+    if !extract_links(&html_content).contains(&url) {
+        return Err(e400("The link is not part of the newsletter issue."));
+    }
+
+    if issue_tracker.is_enabled() {
+        if let Err(e) = record_click(&pool, issue_id, subscriber_id, &url).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to record an issue click."
+            )
+        }
+    }
+
+    Ok(see_other(&url))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue_html_content(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the newsletter issue content.")?;
+    Ok(row.map(|r| r.html_content))
+}
@@ -1,6 +1,8 @@
-use actix_web::HttpResponse;
+use actix_web::{http::header::ContentType, HttpRequest, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
 use anyhow::Context;
-use reqwest::header::LOCATION;
+use askama::Template;
+use reqwest::header::{ACCEPT_LANGUAGE, LOCATION};
 use sqlx::PgPool;
 use uuid::Uuid;
 
@@ -23,6 +25,13 @@ where
     actix_web::error::ErrorBadRequest(e)
 }
 
+pub fn e403<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorForbidden(e)
+}
+
 pub fn e500<T>(e: T) -> actix_web::Error
 where
     T: std::fmt::Debug + std::fmt::Display + 'static,
@@ -36,6 +45,28 @@ pub fn see_other(location: &str) -> HttpResponse {
         .finish()
 }
 
+/// Renders a page; anything interpolated into the template is HTML-escaped.
+pub fn render(template: &impl Template) -> Result<HttpResponse, actix_web::Error> {
+    let body = template.render().map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// The messages for the `flash_messages.html` partial.
+pub fn collect_flash_messages(incoming: &IncomingFlashMessages) -> Vec<String> {
+    incoming.iter().map(|m| m.content().to_string()).collect()
+}
+
+/// The raw `Accept-Language` header, empty if missing or not valid ASCII.
+pub fn accept_language(request: &HttpRequest) -> &str {
+    request
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default()
+}
+
 #[tracing::instrument(name = "Fetching username", skip(user_id, pool))]
 pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
     let row = sqlx::query!(
@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{
+        HeaderMap, HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY,
+        STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+    },
+    web, HttpMessage,
+};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use rand::{thread_rng, Rng};
+
+use crate::routes::e500;
+
+/// A one-off value inline `<script>`s must carry to run under our Content-Security-Policy.
+///
+/// Handlers get it with `web::ReqData<CspNonce>`.
+#[derive(Clone, Debug)]
+pub struct CspNonce(String);
+
+impl CspNonce {
+    fn generate() -> Self {
+        Self(base64::encode(thread_rng().gen::<[u8; 16]>()))
+    }
+}
+
+impl AsRef<str> for CspNonce {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The headers added to every response.
+#[derive(Debug)]
+pub struct SecurityHeaders {
+    /// Every `{nonce}` is replaced with the nonce of the request.
+    content_security_policy: String,
+    frame_options: HeaderValue,
+    referrer_policy: HeaderValue,
+    strict_transport_security: Option<HeaderValue>,
+}
+
+impl SecurityHeaders {
+    /// HSTS is left out if `hsts_max_age` is missing.
+    pub fn new(
+        content_security_policy: String,
+        frame_options: &str,
+        referrer_policy: &str,
+        hsts_max_age: Option<Duration>,
+    ) -> Result<Self, anyhow::Error> {
+        HeaderValue::from_str(&content_security_policy)
+            .context("The Content-Security-Policy is not a valid header value.")?;
+        let frame_options = HeaderValue::from_str(frame_options)
+            .context("The X-Frame-Options is not a valid header value.")?;
+        let referrer_policy = HeaderValue::from_str(referrer_policy)
+            .context("The Referrer-Policy is not a valid header value.")?;
+        let strict_transport_security = hsts_max_age.map(|max_age| {
+            HeaderValue::from_str(&format!("max-age={}; includeSubDomains", max_age.as_secs()))
+                .unwrap()
+        });
+
+        Ok(Self {
+            content_security_policy,
+            frame_options,
+            referrer_policy,
+            strict_transport_security,
+        })
+    }
+
+    /// Sets any of our headers the handler hasn't set itself.
+    fn apply(&self, headers: &mut HeaderMap, nonce: &CspNonce) {
+        let content_security_policy = self.content_security_policy.replace("{nonce}", &nonce.0);
+        let mut set = |name: HeaderName, value: HeaderValue| {
+            if !headers.contains_key(&name) {
+                headers.insert(name, value);
+            }
+        };
+
+        // checked in `new`, and the nonce is plain base64
+        set(
+            CONTENT_SECURITY_POLICY,
+            HeaderValue::from_str(&content_security_policy).unwrap(),
+        );
+        set(X_FRAME_OPTIONS, self.frame_options.clone());
+        set(REFERRER_POLICY, self.referrer_policy.clone());
+        set(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+        if let Some(strict_transport_security) = &self.strict_transport_security {
+            set(STRICT_TRANSPORT_SECURITY, strict_transport_security.clone());
+        }
+    }
+}
+
+pub async fn add_security_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let security_headers = req
+        .app_data::<web::Data<SecurityHeaders>>()
+        .cloned()
+        .context("The security headers are not registered.")
+        .map_err(e500)?;
+    let nonce = CspNonce::generate();
+    req.extensions_mut().insert(nonce.clone());
+
+    // errors returned by inner middleware skip this, so ours respond with `Ok` instead
+    let mut response = next.call(req).await?;
+    security_headers.apply(response.headers_mut(), &nonce);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::http::header::{
+        HeaderMap, HeaderValue, CONTENT_SECURITY_POLICY, STRICT_TRANSPORT_SECURITY, X_FRAME_OPTIONS,
+    };
+    use claim::assert_err;
+
+    use super::{CspNonce, SecurityHeaders};
+
+    fn security_headers(hsts_max_age: Option<Duration>) -> SecurityHeaders {
+        SecurityHeaders::new(
+            "script-src 'nonce-{nonce}'".into(),
+            "DENY",
+            "same-origin",
+            hsts_max_age,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn the_nonce_is_filled_into_the_policy() {
+        let mut headers = HeaderMap::new();
+        let nonce = CspNonce::generate();
+        security_headers(None).apply(&mut headers, &nonce);
+        assert_eq!(
+            headers.get(CONTENT_SECURITY_POLICY).unwrap(),
+            &format!("script-src 'nonce-{}'", nonce.as_ref())
+        );
+    }
+
+    #[test]
+    fn hsts_is_only_sent_when_enabled() {
+        let mut headers = HeaderMap::new();
+        security_headers(None).apply(&mut headers, &CspNonce::generate());
+        assert!(!headers.contains_key(STRICT_TRANSPORT_SECURITY));
+
+        security_headers(Some(Duration::from_secs(60))).apply(&mut headers, &CspNonce::generate());
+        assert_eq!(
+            headers.get(STRICT_TRANSPORT_SECURITY).unwrap(),
+            "max-age=60; includeSubDomains"
+        );
+    }
+
+    #[test]
+    fn headers_set_by_the_handler_are_kept() {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_FRAME_OPTIONS, HeaderValue::from_static("SAMEORIGIN"));
+        security_headers(None).apply(&mut headers, &CspNonce::generate());
+        assert_eq!(headers.get(X_FRAME_OPTIONS).unwrap(), "SAMEORIGIN");
+    }
+
+    #[test]
+    fn invalid_header_values_are_rejected() {
+        assert_err!(SecurityHeaders::new(
+            "default-src 'self'\n".into(),
+            "DENY",
+            "same-origin",
+            None
+        ));
+    }
+}
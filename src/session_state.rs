@@ -3,12 +3,15 @@ use std::future::{ready, Ready};
 use actix_session::{Session, SessionExt};
 use actix_web::FromRequest;
 use anyhow::Context;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use uuid::Uuid;
 
 pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -26,6 +29,45 @@ impl TypedSession {
             .context("Failed to retrieve session state")
     }
 
+    /// The id of the row tracking this session in `user_sessions`.
+    pub fn insert_session_id(&self, session_id: Uuid) -> Result<(), anyhow::Error> {
+        self.0
+            .insert(Self::SESSION_ID_KEY, session_id)
+            .context("Failed to update session state.")
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, anyhow::Error> {
+        self.0
+            .get(Self::SESSION_ID_KEY)
+            .context("Failed to retrieve session state")
+    }
+
+    /// The synchronizer token admin forms must send back, created on first use.
+    pub fn csrf_token(&self) -> Result<String, anyhow::Error> {
+        match self.get_csrf_token()? {
+            Some(csrf_token) => Ok(csrf_token),
+            None => self.rotate_csrf_token(),
+        }
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, anyhow::Error> {
+        self.0
+            .get(Self::CSRF_TOKEN_KEY)
+            .context("Failed to retrieve session state")
+    }
+
+    pub fn rotate_csrf_token(&self) -> Result<String, anyhow::Error> {
+        let mut rng = thread_rng();
+        let csrf_token: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(32)
+            .collect();
+        self.0
+            .insert(Self::CSRF_TOKEN_KEY, &csrf_token)
+            .context("Failed to update session state.")?;
+        Ok(csrf_token)
+    }
+
     pub fn logout(&self) {
         self.0.purge()
     }
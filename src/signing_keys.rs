@@ -0,0 +1,280 @@
+use actix_web::{
+    body::MessageBody,
+    cookie::{Cookie, CookieJar, Key},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderValue, COOKIE},
+    web,
+};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use hmac::{digest::MacError, Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+use crate::{configuration::SessionSettings, routes::e500};
+
+/// The name `CookieMessageStore` gives the flash message cookie.
+const FLASH_COOKIE_NAME: &str = "_flash";
+
+/// The secret everything is signed with, and the ones it replaced.
+///
+/// Signatures made with a previous secret are still accepted, so it can be rotated without
+/// logging everyone out or breaking the links in delivered issues. A previous secret should stay
+/// listed for at least as long as anything signed with it is in use.
+#[derive(Clone)]
+pub struct SigningKeys {
+    current: Secret<String>,
+    previous: Vec<Secret<String>>,
+}
+
+impl SigningKeys {
+    pub fn new(current: Secret<String>, previous: Vec<Secret<String>>) -> Self {
+        Self { current, previous }
+    }
+
+    /// The current secret first.
+    fn all(&self) -> impl Iterator<Item = &Secret<String>> {
+        std::iter::once(&self.current).chain(&self.previous)
+    }
+
+    /// A MAC keyed with the current secret, to sign with.
+    pub fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(self.current.expose_secret().as_bytes()).unwrap()
+    }
+
+    /// Checks `tag` against the MAC of each secret in turn, fed with `update`.
+    pub fn verify(&self, tag: &[u8], update: impl Fn(&mut Hmac<Sha256>)) -> Result<(), MacError> {
+        self.all()
+            .map(|secret| {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes()).unwrap();
+                update(&mut mac);
+                mac.verify_slice(tag)
+            })
+            .find(Result::is_ok)
+            .unwrap_or(Err(MacError))
+    }
+
+    /// The key cookies are signed and encrypted with.
+    ///
+    /// Panics if the current secret is shorter than 64 bytes, which `Settings::validate` rules out.
+    pub fn cookie_key(&self) -> Key {
+        Key::from(self.current.expose_secret().as_bytes())
+    }
+
+    fn previous_cookie_keys(&self) -> impl Iterator<Item = Key> + '_ {
+        self.previous
+            .iter()
+            .map(|secret| Key::from(secret.expose_secret().as_bytes()))
+    }
+
+    /// Re-signs `cookie` with the current key if it was signed with a previous one.
+    ///
+    /// `None` if there was nothing to do, or it wasn't signed with any of our keys.
+    fn rekey(&self, cookie: &Cookie<'static>, protection: Protection) -> Option<Cookie<'static>> {
+        let current = self.cookie_key();
+        if protection.open(&current, cookie.clone()).is_some() {
+            return None;
+        }
+        let opened = self
+            .previous_cookie_keys()
+            .find_map(|key| protection.open(&key, cookie.clone()))?;
+        Some(protection.seal(&current, opened))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Protection {
+    /// How `SessionMiddleware` stores the session key.
+    Private,
+    /// How `CookieMessageStore` stores flash messages.
+    Signed,
+}
+
+impl Protection {
+    fn open(&self, key: &Key, cookie: Cookie<'static>) -> Option<Cookie<'static>> {
+        let jar = CookieJar::new();
+        match self {
+            Protection::Private => jar.private(key).decrypt(cookie),
+            Protection::Signed => jar.signed(key).verify(cookie),
+        }
+    }
+
+    fn seal(&self, key: &Key, cookie: Cookie<'static>) -> Cookie<'static> {
+        let name = cookie.name().to_string();
+        let mut jar = CookieJar::new();
+        match self {
+            Protection::Private => jar.private_mut(key).add(cookie),
+            Protection::Signed => jar.signed_mut(key).add(cookie),
+        }
+        jar.get(&name).unwrap().clone()
+    }
+}
+
+/// Rewrites the `Cookie` headers so cookies signed with a previous key reach the session and
+/// flash message middleware signed with the current one.
+///
+/// They are signed with the current key again whenever they are next set.
+fn rekey_cookie_header<'a>(
+    headers: impl Iterator<Item = &'a HeaderValue>,
+    keys: &SigningKeys,
+    session_cookie_name: &str,
+) -> Option<HeaderValue> {
+    let mut rekeyed = false;
+    let cookies: Vec<_> = headers
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| Cookie::parse_encoded(cookie.trim().to_string()).ok())
+        .map(|cookie| {
+            let protection = match cookie.name() {
+                name if name == session_cookie_name => Protection::Private,
+                FLASH_COOKIE_NAME => Protection::Signed,
+                _ => return cookie,
+            };
+            match keys.rekey(&cookie, protection) {
+                Some(cookie) => {
+                    rekeyed = true;
+                    cookie
+                }
+                None => cookie,
+            }
+        })
+        .collect();
+    if !rekeyed {
+        return None;
+    }
+
+    let header = cookies
+        .iter()
+        .map(|cookie| cookie.encoded().stripped().to_string())
+        .collect::<Vec<_>>()
+        .join("; ");
+    HeaderValue::from_str(&header).ok()
+}
+
+/// Must run before anything reads the request cookies, as actix-web parses them only once.
+pub async fn rekey_cookies(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let keys = req
+        .app_data::<web::Data<SigningKeys>>()
+        .cloned()
+        .context("The signing keys are not registered.")
+        .map_err(e500)?;
+    let session_settings = req
+        .app_data::<web::Data<SessionSettings>>()
+        .cloned()
+        .context("The session settings are not registered.")
+        .map_err(e500)?;
+
+    if !keys.previous.is_empty() {
+        if let Some(header) = rekey_cookie_header(
+            req.headers().get_all(COOKIE),
+            &keys,
+            &session_settings.cookie_name,
+        ) {
+            req.headers_mut().insert(COOKIE, header);
+        }
+    }
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{cookie::Cookie, http::header::HeaderValue};
+    use claim::{assert_err, assert_none, assert_ok};
+    use hmac::Mac;
+    use secrecy::Secret;
+
+    use super::{rekey_cookie_header, Protection, SigningKeys};
+
+    /// Long enough to make a cookie key of.
+    fn secret(c: char) -> Secret<String> {
+        Secret::new(c.to_string().repeat(64))
+    }
+
+    fn sealed(c: char, protection: Protection, name: &str, value: &str) -> Cookie<'static> {
+        let key = SigningKeys::new(secret(c), vec![]).cookie_key();
+        protection.seal(&key, Cookie::new(name.to_string(), value.to_string()))
+    }
+
+    fn header(cookies: &[Cookie<'static>]) -> HeaderValue {
+        let header = cookies
+            .iter()
+            .map(|cookie| cookie.encoded().stripped().to_string())
+            .collect::<Vec<_>>()
+            .join("; ");
+        HeaderValue::from_str(&header).unwrap()
+    }
+
+    fn tag(keys: &SigningKeys, message: &[u8]) -> Vec<u8> {
+        let mut mac = keys.mac();
+        mac.update(message);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    #[test]
+    fn tags_made_with_a_previous_secret_are_accepted() {
+        let old = SigningKeys::new(secret('a'), vec![]);
+        let rotated = SigningKeys::new(secret('b'), vec![secret('a')]);
+        let tag = tag(&old, b"message");
+
+        assert_ok!(rotated.verify(&tag, |mac| mac.update(b"message")));
+        assert_err!(rotated.verify(&tag, |mac| mac.update(b"another message")));
+        assert_err!(
+            SigningKeys::new(secret('b'), vec![]).verify(&tag, |mac| mac.update(b"message"))
+        );
+    }
+
+    #[test]
+    fn new_tags_are_made_with_the_current_secret() {
+        let rotated = SigningKeys::new(secret('b'), vec![secret('a')]);
+        let tag = tag(&rotated, b"message");
+
+        assert_ok!(SigningKeys::new(secret('b'), vec![]).verify(&tag, |mac| mac.update(b"message")));
+        assert_err!(
+            SigningKeys::new(secret('a'), vec![]).verify(&tag, |mac| mac.update(b"message"))
+        );
+    }
+
+    #[test]
+    fn cookies_sealed_with_a_previous_key_are_resealed_with_the_current_one() {
+        let keys = SigningKeys::new(secret('b'), vec![secret('a')]);
+        let cookies = [
+            sealed('a', Protection::Private, "id", "session-key"),
+            sealed('a', Protection::Signed, "_flash", r#"[{"content":"Hi"}]"#),
+            Cookie::new("theme", "dark"),
+        ];
+
+        let header = rekey_cookie_header([header(&cookies)].iter(), &keys, "id").unwrap();
+
+        let current = keys.cookie_key();
+        let cookies: Vec<_> = header
+            .to_str()
+            .unwrap()
+            .split("; ")
+            .map(|cookie| Cookie::parse_encoded(cookie.to_string()).unwrap())
+            .collect();
+        let session = Protection::Private
+            .open(&current, cookies[0].clone())
+            .unwrap();
+        assert_eq!(session.value(), "session-key");
+        let flash = Protection::Signed
+            .open(&current, cookies[1].clone())
+            .unwrap();
+        assert_eq!(flash.value(), r#"[{"content":"Hi"}]"#);
+        assert_eq!(cookies[2].value(), "dark");
+    }
+
+    #[test]
+    fn cookies_sealed_with_the_current_or_an_unknown_key_are_left_alone() {
+        let keys = SigningKeys::new(secret('b'), vec![secret('a')]);
+        let cookies = [
+            sealed('b', Protection::Private, "id", "session-key"),
+            sealed('c', Protection::Signed, "_flash", "[]"),
+        ];
+
+        assert_none!(rekey_cookie_header([header(&cookies)].iter(), &keys, "id"));
+    }
+}
@@ -1,9 +1,8 @@
 use std::net::TcpListener;
+use std::time::Duration;
 
 use actix_session::storage::RedisSessionStore;
-use actix_session::SessionMiddleware;
-use actix_web::cookie::Key;
-use actix_web::dev::Server;
+use actix_web::dev::{Server, ServerHandle};
 use actix_web::web;
 use actix_web::App;
 use actix_web::HttpServer;
@@ -16,10 +15,24 @@ use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use tracing_actix_web::TracingLogger;
 
-use crate::configuration::Settings;
+use crate::client_ip::TrustedProxies;
+use crate::configuration::{
+    AuthenticationSettings, SessionSettings, Settings, SubscriptionSettings,
+};
+use crate::domain::EmailValidator;
 use crate::email_client::EmailClient;
+use crate::i18n::Localizer;
+use crate::metrics::record_http_metrics;
+use crate::migrations::run_migrations;
+use crate::request_id::{add_request_id, RequestIdRootSpanBuilder};
 use crate::routes::*;
-use crate::{authentication::reject_anonymous_users, configuration::DatabaseSettings};
+use crate::security_headers::{add_security_headers, SecurityHeaders};
+use crate::signing_keys::{rekey_cookies, SigningKeys};
+use crate::tracking::IssueTracker;
+use crate::{
+    authentication::{bootstrap_admin, reject_anonymous_users, reject_forged_requests},
+    configuration::DatabaseSettings,
+};
 
 pub struct Application {
     port: u16,
@@ -27,27 +40,59 @@ pub struct Application {
 }
 
 pub struct ApplicationBaseUrl(pub String);
-pub struct HmacSecret(pub Secret<String>);
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+        if configuration.database.migrate_on_startup {
+            run_migrations(&configuration.database).await?;
+        }
         let connection_pool = get_connection_pool(&configuration.database);
+        if let Some(credential) = bootstrap_admin(&connection_pool).await? {
+            // the only place this password is ever shown. `admin_login` rather than `username`
+            // so that redaction leaves it readable: the credential is no use without it
+            tracing::warn!(
+                admin_login = %credential.username,
+                one_time_password = %credential.password.expose_secret(),
+                "Created the first admin user with a ONE-TIME password. \
+                It must be changed on first login and is not shown again."
+            );
+        }
         // interesting subtlety - because the EmailClientSettings authorization_token's type doesn't implement Copy (Secret)
         // whenever we move the value somewhere it results in a "Partial Move" see: https://doc.rust-lang.org/rust-by-example/scope/move/partial_move.html
         // meaning we can access unmoved values but not the moved member of the struct OR the struct as a whole
         // since our `timeout()` method takes &self, we need to make sure we call it BEFORE we partially move it
-        let email_client = configuration.email_client.client();
+        let email_client = configuration.email_client.client()?;
+        let email_validator = configuration.email_validation.validator()?;
+        backfill_canonical_emails(&connection_pool, &email_validator).await?;
         let address = configuration.application.get_address();
+        let shutdown_timeout = configuration.application.shutdown_timeout();
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
+        let security_headers = configuration
+            .security_headers
+            .headers(&configuration.application.base_url)?;
+        let signing_keys = configuration.application.signing_keys();
+        let issue_tracker = IssueTracker::new(
+            configuration.application.base_url.clone(),
+            signing_keys.clone(),
+            configuration.tracking.enabled,
+        );
 
         let server = run(
             listener,
             connection_pool,
             email_client,
+            email_validator,
             configuration.application.base_url.clone(),
-            HmacSecret(configuration.application.hmac_secret),
+            configuration.application.trusted_proxies(),
+            signing_keys,
             configuration.redis_uri,
+            configuration.session,
+            security_headers,
+            issue_tracker,
+            configuration.subscriptions,
+            configuration.authentication,
+            shutdown_timeout,
         )
         .await?;
 
@@ -58,6 +103,53 @@ impl Application {
         self.port
     }
 
+    /// Lets the caller stop the server, e.g. on a shutdown signal.
+    pub fn server_handle(&self) -> ServerHandle {
+        self.server.handle()
+    }
+
+    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+        self.server.await
+    }
+}
+
+/// Serves `/metrics` for a delivery worker running on its own, without the API to serve them.
+pub struct MetricsServer {
+    port: u16,
+    server: Server,
+}
+
+impl MetricsServer {
+    pub fn build(
+        listener: TcpListener,
+        db_pool: PgPool,
+        shutdown_timeout: Duration,
+    ) -> Result<Self, std::io::Error> {
+        let port = listener.local_addr()?.port();
+        let db_pool = web::Data::new(db_pool);
+        let server = HttpServer::new(move || {
+            App::new()
+                .wrap(TracingLogger::default())
+                .route("/metrics", web::get().to(metrics))
+                .app_data(db_pool.clone())
+        })
+        // the worker's shutdown signal stops it, see `main`
+        .disable_signals()
+        .shutdown_timeout(shutdown_timeout.as_secs())
+        .listen(listener)?
+        .run();
+
+        Ok(Self { port, server })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn server_handle(&self) -> ServerHandle {
+        self.server.handle()
+    }
+
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
         self.server.await
     }
@@ -70,58 +162,109 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
         .connect_lazy_with(configuration.with_db())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
+    email_validator: EmailValidator,
     base_url: String,
-    hmac_secret: HmacSecret,
+    trusted_proxies: TrustedProxies,
+    signing_keys: SigningKeys,
     redis_uri: Secret<String>,
+    session_settings: SessionSettings,
+    security_headers: SecurityHeaders,
+    issue_tracker: IssueTracker,
+    subscription_settings: SubscriptionSettings,
+    authentication_settings: AuthenticationSettings,
+    shutdown_timeout: Duration,
 ) -> Result<Server, anyhow::Error> {
     let db_pool = web::Data::new(db_pool); // this is just a fancy Arc
     let email_client = web::Data::new(email_client);
+    let email_validator = web::Data::new(email_validator);
+    let localizer = web::Data::new(Localizer::new());
     let application_base_url = web::Data::new(ApplicationBaseUrl(base_url));
-    let secret_key = Key::from(hmac_secret.0.expose_secret().as_bytes());
+    let trusted_proxies = web::Data::new(trusted_proxies);
+    let secret_key = signing_keys.cookie_key();
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
-    let hmac_secret = web::Data::new(hmac_secret);
+    let form_tokens = web::Data::new(subscription_settings.form_tokens(signing_keys.clone()));
+    let challenge_verifier = web::Data::from(subscription_settings.challenge_verifier());
+    let subscription_settings = web::Data::new(subscription_settings);
+    let authentication_settings = web::Data::new(authentication_settings);
+    let signing_keys = web::Data::new(signing_keys);
+    let issue_tracker = web::Data::new(issue_tracker);
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    let redis_client = web::Data::new(redis::Client::open(redis_uri.expose_secret().as_str())?);
+    let session_settings = web::Data::new(session_settings);
+    let security_headers = web::Data::new(security_headers);
     // HttpServer handles all transport-level concerns (port binding, TLS, connections, etc.)
     let server = HttpServer::new(move || {
         // App handles logic (routing, request handling, etc.)
         App::new()
             .wrap(message_framework.clone())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
-            .wrap(TracingLogger::default())
+            .wrap(session_settings.middleware(redis_store.clone(), secret_key.clone()))
+            .wrap(from_fn(rekey_cookies))
+            .wrap(from_fn(add_security_headers))
+            .wrap(from_fn(record_http_metrics))
+            .wrap(from_fn(add_request_id))
+            .wrap(TracingLogger::<RequestIdRootSpanBuilder>::new())
             // .route("/", Route::new().guard(Guard::get()).to(_))
             .route("/", web::get().to(home))
             .route("/health_check", web::get().to(health_check))
+            .route("/health/live", web::get().to(health_check))
+            .route("/health/ready", web::get().to(readiness))
+            .route("/metrics", web::get().to(metrics))
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
             .service(
                 web::scope("/admin")
+                    // the last one wrapped runs first, so only signed in users reach the CSRF check
+                    .wrap(from_fn(reject_forged_requests))
                     .wrap(from_fn(reject_anonymous_users))
                     .route("/logout", web::post().to(logout))
                     .route("/dashboard", web::get().to(admin_dashboard))
                     .route("/password", web::get().to(change_password_form))
                     .route("/password", web::post().to(change_password))
                     .route("/newsletters", web::get().to(publish_newsletter_form))
-                    .route("/newsletters", web::post().to(publish_newsletter)),
+                    .route("/newsletters", web::post().to(publish_newsletter))
+                    .route("/sessions", web::get().to(sessions_page))
+                    .route("/sessions/revoke", web::post().to(revoke_session))
+                    .route("/sessions/revoke-all", web::post().to(logout_everywhere)),
             )
             .route("/subscriptions", web::post().to(subscribe))
             .route(
                 "/subscriptions/confirm",
                 web::get().to(confirm_subscription),
             )
+            .route(
+                "/newsletters/{newsletter_issue_id}/open",
+                web::get().to(track_open),
+            )
+            .route(
+                "/newsletters/{newsletter_issue_id}/click",
+                web::get().to(track_click),
+            )
             .app_data(db_pool.clone())
+            .app_data(redis_client.clone())
             .app_data(email_client.clone())
+            .app_data(email_validator.clone())
+            .app_data(localizer.clone())
             .app_data(application_base_url.clone())
-            .app_data(hmac_secret.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(signing_keys.clone())
+            .app_data(issue_tracker.clone())
+            .app_data(form_tokens.clone())
+            .app_data(challenge_verifier.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(authentication_settings.clone())
+            .app_data(session_settings.clone())
+            .app_data(security_headers.clone())
     })
     // .bind(address)? // we can have the server create a listener for us
+    // signals are handled in `main` so the API and the worker stop together
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
     .listen(listener)?
     .run();
 
@@ -1,24 +1,48 @@
+use std::collections::HashMap;
+
+use opentelemetry::{
+    global,
+    sdk::{
+        propagation::TraceContextPropagator,
+        trace::{self, BatchSpanProcessor, Tracer, TracerProvider},
+        Resource,
+    },
+    trace::{TraceError, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
 use tokio::task::JoinHandle;
-use tracing::{subscriber::set_global_default, Subscriber};
+use tracing::{subscriber::set_global_default, Span, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::{
     fmt::MakeWriter, prelude::__tracing_subscriber_SubscriberExt, EnvFilter, Registry,
 };
 
+use crate::redaction::{RedactingMakeWriter, RedactingSpanProcessor, Redaction};
+
+/// Spans are also exported through `tracer`, if there is one.
+///
+/// Personal data is redacted from what is written to `sink` as `redaction` says. The spans
+/// given to `tracer` are redacted by its own processor, see [`otlp_tracer`].
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer: Option<Tracer>,
+    redaction: Redaction,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
-    let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let formatting_layer =
+        BunyanFormattingLayer::new(name, RedactingMakeWriter::new(sink, redaction));
     Registry::default()
         .with(env_filter)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
@@ -26,6 +50,56 @@ where
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
+    // lets `TracingLogger` pick up the trace of incoming `traceparent` headers
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+/// Exports spans in batches to an OpenTelemetry collector over OTLP/gRPC.
+///
+/// Personal data is redacted from the spans before they are exported, as `redaction` says.
+///
+/// Must be called from within a Tokio runtime.
+pub fn otlp_tracer(
+    service_name: String,
+    endpoint: &str,
+    redaction: Redaction,
+) -> Result<Tracer, TraceError> {
+    let exporter = SpanExporterBuilder::from(
+        opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(endpoint),
+    )
+    .build_span_exporter()?;
+    let batches = BatchSpanProcessor::builder(exporter, opentelemetry::runtime::Tokio).build();
+    let provider = TracerProvider::builder()
+        .with_span_processor(RedactingSpanProcessor::new(batches, redaction))
+        .with_config(
+            trace::config()
+                .with_resource(Resource::new([KeyValue::new("service.name", service_name)])),
+        )
+        .build();
+    let tracer = provider.tracer("opentelemetry-otlp");
+    // so that `global::shutdown_tracer_provider` flushes it on the way out
+    global::set_tracer_provider(provider);
+    Ok(tracer)
+}
+
+/// The W3C `traceparent` of the current span, to carry its trace past a queue.
+///
+/// `None` unless spans are being exported.
+pub fn current_traceparent() -> Option<String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut carrier)
+    });
+    carrier.remove("traceparent")
+}
+
+/// Makes the current span part of the trace `traceparent` was taken from.
+pub fn continue_trace(traceparent: &str) {
+    let carrier = HashMap::from([("traceparent".to_string(), traceparent.to_string())]);
+    let context = global::get_text_map_propagator(|propagator| propagator.extract(&carrier));
+    Span::current().set_parent(context);
 }
 
 pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
@@ -36,3 +110,48 @@ where
     let current_span = tracing::Span::current();
     tokio::task::spawn_blocking(move || current_span.in_scope(f))
 }
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::{
+        global,
+        sdk::{propagation::TraceContextPropagator, trace::TracerProvider},
+        trace::{TraceContextExt, TracerProvider as _},
+    };
+    use tracing::{info_span, Span};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, Registry};
+
+    use super::{continue_trace, current_traceparent};
+
+    fn trace_id(span: &Span) -> String {
+        span.context().span().span_context().trace_id().to_string()
+    }
+
+    #[test]
+    fn a_trace_can_be_continued_from_its_traceparent() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = TracerProvider::builder().build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let publisher = info_span!("publisher");
+            let traceparent = publisher.in_scope(current_traceparent).unwrap();
+
+            let worker = info_span!("worker");
+            worker.in_scope(|| continue_trace(&traceparent));
+            assert_eq!(trace_id(&worker), trace_id(&publisher));
+            assert_ne!(trace_id(&info_span!("unrelated")), trace_id(&publisher));
+        });
+    }
+
+    #[test]
+    fn there_is_no_traceparent_when_spans_are_not_exported() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        tracing::subscriber::with_default(Registry::default(), || {
+            let _span = info_span!("publisher").entered();
+            assert_eq!(current_traceparent(), None);
+        });
+    }
+}
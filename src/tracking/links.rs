@@ -0,0 +1,92 @@
+use std::ops::Range;
+
+/// Returns the absolute http(s) link targets of every `href` in `html`, with HTML entities decoded.
+pub fn extract_links(html: &str) -> Vec<String> {
+    find_links(html).into_iter().map(|(_, url)| url).collect()
+}
+
+/// Replaces the target of every absolute http(s) `href` in `html` with the output of `rewrite`.
+pub(crate) fn rewrite_links<F>(html: &str, rewrite: F) -> String
+where
+    F: Fn(&str) -> String,
+{
+    let mut rewritten = String::with_capacity(html.len());
+    let mut cursor = 0;
+    for (range, url) in find_links(html) {
+        rewritten.push_str(&html[cursor..range.start]);
+        // the only character a tracking url carries that is special inside an attribute
+        rewritten.push_str(&rewrite(&url).replace('&', "&amp;"));
+        cursor = range.end;
+    }
+    rewritten.push_str(&html[cursor..]);
+    rewritten
+}
+
+fn find_links(html: &str) -> Vec<(Range<usize>, String)> {
+    // ASCII lowercasing keeps byte offsets identical to the original
+    let lowercase = html.to_ascii_lowercase();
+    let mut links = Vec::new();
+    let mut cursor = 0;
+
+    while let Some(i) = lowercase[cursor..].find("href=") {
+        let start = cursor + i + "href=".len();
+        cursor = start;
+
+        let quote = match html[start..].chars().next() {
+            Some(q @ ('"' | '\'')) => q,
+            _ => continue,
+        };
+        let value_start = start + 1;
+        let value_end = match html[value_start..].find(quote) {
+            Some(len) => value_start + len,
+            None => break,
+        };
+        cursor = value_end;
+
+        let raw = &html[value_start..value_end];
+        let url = htmlescape::decode_html(raw).unwrap_or_else(|_| raw.to_string());
+        if url.starts_with("http://") || url.starts_with("https://") {
+            links.push((value_start..value_end, url));
+        }
+    }
+
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_in_either_quote_style_are_extracted() {
+        let html =
+            r#"<a href="https://a.example.com">a</a> <a HREF='http://b.example.com/x'>b</a>"#;
+        assert_eq!(
+            extract_links(html),
+            vec!["https://a.example.com", "http://b.example.com/x"]
+        );
+    }
+
+    #[test]
+    fn relative_anchor_and_mailto_links_are_ignored() {
+        let html = r##"<a href="#top">t</a><a href="mailto:a@b.com">m</a><a href="/about">r</a>"##;
+        assert!(extract_links(html).is_empty());
+    }
+
+    #[test]
+    fn html_entities_in_links_are_decoded() {
+        let html = r#"<a href="https://example.com/?a=1&amp;b=2">x</a>"#;
+        assert_eq!(extract_links(html), vec!["https://example.com/?a=1&b=2"]);
+    }
+
+    #[test]
+    fn rewriting_only_touches_link_targets() {
+        let html =
+            r#"<p>Read <a href="https://example.com">this</a> &amp; <a href="/local">that</a></p>"#;
+        let rewritten = rewrite_links(html, |url| format!("https://t.example.com/?u={url}&x=1"));
+        assert_eq!(
+            rewritten,
+            r#"<p>Read <a href="https://t.example.com/?u=https://example.com&amp;x=1">this</a> &amp; <a href="/local">that</a></p>"#
+        );
+    }
+}
@@ -0,0 +1,7 @@
+mod links;
+mod persistence;
+mod tracker;
+
+pub use links::extract_links;
+pub use persistence::{get_issue_stats, record_click, record_open, IssueStats};
+pub use tracker::IssueTracker;
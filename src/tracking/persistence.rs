@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct IssueStats {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+    pub unique_opens: i64,
+    pub total_opens: i64,
+    pub unique_clicks: i64,
+    pub total_clicks: i64,
+}
+
+/// Records an open, unless tracking has been switched off for the issue.
+#[tracing::instrument(name = "Recording issue open", skip(pool))]
+pub async fn record_open(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_opens (newsletter_issue_id, subscriber_id, opened_at)
+        SELECT newsletter_issue_id, $2, now()
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1 AND
+            tracking_enabled
+        "#,
+        issue_id,
+        subscriber_id,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Records a click, unless tracking has been switched off for the issue.
+#[tracing::instrument(name = "Recording issue click", skip(pool))]
+pub async fn record_click(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    url: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_clicks (newsletter_issue_id, subscriber_id, url, clicked_at)
+        SELECT newsletter_issue_id, $2, $3, now()
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1 AND
+            tracking_enabled
+        "#,
+        issue_id,
+        subscriber_id,
+        url,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Retrieving issue statistics", skip(pool))]
+pub async fn get_issue_stats(pool: &PgPool) -> Result<Vec<IssueStats>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueStats,
+        r#"
+        SELECT
+            n.newsletter_issue_id,
+            n.title,
+            n.published_at,
+            (SELECT COUNT(DISTINCT o.subscriber_id) FROM issue_opens o
+                WHERE o.newsletter_issue_id = n.newsletter_issue_id) as "unique_opens!",
+            (SELECT COUNT(*) FROM issue_opens o
+                WHERE o.newsletter_issue_id = n.newsletter_issue_id) as "total_opens!",
+            (SELECT COUNT(DISTINCT c.subscriber_id) FROM issue_clicks c
+                WHERE c.newsletter_issue_id = n.newsletter_issue_id) as "unique_clicks!",
+            (SELECT COUNT(*) FROM issue_clicks c
+                WHERE c.newsletter_issue_id = n.newsletter_issue_id) as "total_clicks!"
+        FROM newsletter_issues n
+        WHERE n.tracking_enabled
+        ORDER BY n.published_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,185 @@
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{signing_keys::SigningKeys, tracking::links::rewrite_links};
+
+/// Builds and verifies the signed open/click links embedded in delivered issues.
+#[derive(Clone)]
+pub struct IssueTracker {
+    base_url: String,
+    signing_keys: SigningKeys,
+    enabled: bool,
+}
+
+impl IssueTracker {
+    pub fn new(base_url: String, signing_keys: SigningKeys, enabled: bool) -> Self {
+        Self {
+            base_url,
+            signing_keys,
+            enabled,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Routes every link of `html_content` through the click endpoint and appends an open pixel.
+    pub fn instrument(&self, issue_id: Uuid, subscriber_id: Uuid, html_content: &str) -> String {
+        let mut html = rewrite_links(html_content, |url| {
+            self.click_url(issue_id, subscriber_id, url)
+        });
+
+        let pixel = format!(
+            r#"<img src="{}" width="1" height="1" alt="" />"#,
+            self.open_url(issue_id, subscriber_id).replace('&', "&amp;")
+        );
+        match html.to_ascii_lowercase().rfind("</body>") {
+            Some(i) => html.insert_str(i, &pixel),
+            None => html.push_str(&pixel),
+        }
+        html
+    }
+
+    pub fn open_url(&self, issue_id: Uuid, subscriber_id: Uuid) -> String {
+        format!(
+            "{}/newsletters/{}/open?subscriber_id={}&tag={}",
+            self.base_url,
+            issue_id,
+            subscriber_id,
+            self.tag(issue_id, subscriber_id, None)
+        )
+    }
+
+    pub fn click_url(&self, issue_id: Uuid, subscriber_id: Uuid, url: &str) -> String {
+        format!(
+            "{}/newsletters/{}/click?subscriber_id={}&url={}&tag={}",
+            self.base_url,
+            issue_id,
+            subscriber_id,
+            urlencoding::Encoded::new(url),
+            self.tag(issue_id, subscriber_id, Some(url))
+        )
+    }
+
+    pub fn verify_open(
+        &self,
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        tag: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.verify(issue_id, subscriber_id, None, tag)
+    }
+
+    pub fn verify_click(
+        &self,
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: &str,
+        tag: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.verify(issue_id, subscriber_id, Some(url), tag)
+    }
+
+    fn verify(
+        &self,
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: Option<&str>,
+        tag: &str,
+    ) -> Result<(), anyhow::Error> {
+        let tag = hex::decode(tag).context("The tracking tag is not valid hex.")?;
+        self.signing_keys
+            .verify(&tag, |mac| update(mac, issue_id, subscriber_id, url))
+            .context("The tracking tag does not match the link.")
+    }
+
+    fn tag(&self, issue_id: Uuid, subscriber_id: Uuid, url: Option<&str>) -> String {
+        let mut mac = self.signing_keys.mac();
+        update(&mut mac, issue_id, subscriber_id, url);
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+fn update(mac: &mut Hmac<Sha256>, issue_id: Uuid, subscriber_id: Uuid, url: Option<&str>) {
+    mac.update(issue_id.as_bytes());
+    mac.update(subscriber_id.as_bytes());
+    if let Some(url) = url {
+        mac.update(url.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::IssueTracker;
+    use crate::signing_keys::SigningKeys;
+
+    fn tracker() -> IssueTracker {
+        IssueTracker::new(
+            "https://example.com".into(),
+            SigningKeys::new(Secret::new("super-secret".into()), vec![]),
+            true,
+        )
+    }
+
+    fn tag_of(url: &str) -> String {
+        url.rsplit_once("tag=").unwrap().1.to_string()
+    }
+
+    #[test]
+    fn open_tags_verify_for_the_recipient_they_were_issued_to() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let tag = tag_of(&tracker().open_url(issue_id, subscriber_id));
+
+        assert_ok!(tracker().verify_open(issue_id, subscriber_id, &tag));
+        assert_err!(tracker().verify_open(issue_id, Uuid::new_v4(), &tag));
+    }
+
+    #[test]
+    fn click_tags_are_bound_to_the_destination() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let tag = tag_of(&tracker().click_url(issue_id, subscriber_id, "https://a.example.com"));
+
+        assert_ok!(tracker().verify_click(issue_id, subscriber_id, "https://a.example.com", &tag));
+        assert_err!(tracker().verify_click(issue_id, subscriber_id, "https://evil.example", &tag));
+    }
+
+    #[test]
+    fn links_from_before_a_rotation_still_verify() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let tag = tag_of(&tracker().open_url(issue_id, subscriber_id));
+        let rotated = IssueTracker::new(
+            "https://example.com".into(),
+            SigningKeys::new(
+                Secret::new("new-secret".into()),
+                vec![Secret::new("super-secret".into())],
+            ),
+            true,
+        );
+
+        assert_ok!(rotated.verify_open(issue_id, subscriber_id, &tag));
+        assert_ne!(
+            tag_of(&rotated.open_url(issue_id, subscriber_id)),
+            tag,
+            "new links must be signed with the new secret"
+        );
+    }
+
+    #[test]
+    fn instrumenting_adds_a_pixel_before_the_closing_body_tag() {
+        let html = tracker().instrument(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            "<html><body><p>Hi</p></body></html>",
+        );
+
+        assert!(html.contains(r#"<img src="https://example.com/newsletters/"#));
+        assert!(html.ends_with(r#" /></body></html>"#));
+    }
+}
@@ -58,3 +58,26 @@ async fn admin_dashboard_includes_link_to_submit_newsletter() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains("href=\"/admin/newsletters\""));
 }
+
+#[tokio::test]
+async fn usernames_are_escaped_on_the_admin_dashboard() {
+    // Arrange
+    let mut app = spawn_app().await;
+    app.test_user.username = "<script>alert('hi')</script>".into();
+    sqlx::query!(
+        "UPDATE users SET username = $1 WHERE user_id = $2",
+        app.test_user.username,
+        app.test_user.user_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.login().await;
+
+    // Act
+    let html_page = app.get_admin_dashboard_html().await;
+
+    // Assert
+    assert!(!html_page.contains("<script>"));
+    assert!(html_page.contains("Welcome &lt;script&gt;alert(&#x27;hi&#x27;)&lt;/script&gt;!"));
+}
@@ -64,7 +64,7 @@ async fn new_password_fields_must_match() {
     let html = app.get_change_password_html().await;
 
     assert!(html.contains(
-        "<p><i>You entered two different new passwords - the field values must match.</i></p>"
+        "<p><i>You entered two different new passwords - the field values must match. (request id: "
     ))
 }
 
@@ -94,7 +94,7 @@ async fn current_password_must_be_valid() {
 
     // Act 2
     let html = app.get_change_password_html().await;
-    assert!(html.contains("<p><i>You entered an invalid password.</i></p>"))
+    assert!(html.contains("<p><i>You entered an invalid password. (request id: "))
 }
 
 #[tokio::test]
@@ -146,3 +146,80 @@ async fn changing_password_works() {
     let response = app.post_login(&login_body).await;
     assert_is_redirect_to(&response, "/admin/dashboard");
 }
+
+#[tokio::test]
+async fn new_password_must_differ_from_the_current_one() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+
+    // Act 1
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &app.test_user.password,
+            "new_password_check": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Act 2
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page
+        .contains("<p><i>Your new password must be different from the current one. (request id: "));
+}
+
+#[tokio::test]
+async fn users_flagged_for_a_password_change_can_only_change_their_password() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!(
+        "UPDATE users SET must_change_password = true WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.login().await;
+
+    // Act 1 - Everything else redirects to the password form
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>You must choose a new password before continuing.</i></p>"));
+
+    // Act 2 - Change the password
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn expired_passwords_must_be_changed() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!(
+        "UPDATE users SET password_changed_at = now() - interval '1 year' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.login().await;
+
+    // Act
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+}
@@ -0,0 +1,143 @@
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+fn change_password_body(app: &TestApp, new_password: &str) -> serde_json::Value {
+    serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": new_password,
+        "new_password_check": new_password,
+    })
+}
+
+#[tokio::test]
+async fn admin_forms_embed_the_csrf_token() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let csrf_token = app.get_csrf_token().await;
+    assert!(!csrf_token.is_empty());
+
+    // Act
+    let dashboard = app.get_admin_dashboard_html().await;
+    let newsletters = app.get_publish_newsletter_html().await;
+    let sessions = app.get_sessions_html().await;
+
+    // Assert
+    for html_page in [dashboard, newsletters, sessions] {
+        assert!(html_page.contains(&format!(
+            r#"<input type="hidden" name="csrf_token" value="{csrf_token}">"#
+        )));
+    }
+}
+
+#[tokio::test]
+async fn posts_without_a_csrf_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/password", app.address))
+        .form(&change_password_body(&app, &new_password))
+        .send()
+        .await
+        .expect("Failed to send post.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+    // the password was left alone
+    app.login().await;
+}
+
+#[tokio::test]
+async fn posts_with_a_wrong_csrf_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", app.address))
+        .form(&serde_json::json!({ "csrf_token": "not-the-token" }))
+        .send()
+        .await
+        .expect("Failed to send post.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn a_new_csrf_token_is_issued_on_login() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let first_token = app.get_csrf_token().await;
+
+    // Act
+    app.login().await;
+
+    // Assert
+    assert_ne!(app.get_csrf_token().await, first_token);
+}
+
+#[tokio::test]
+async fn posts_from_other_sites_are_rejected_even_with_the_token() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let csrf_token = app.get_csrf_token().await;
+
+    for (header, value) in [
+        ("Origin", "https://evil.example".to_string()),
+        ("Origin", "null".to_string()),
+        (
+            "Referer",
+            "https://evil.example/newsletter.html".to_string(),
+        ),
+    ] {
+        // Act
+        let response = app
+            .api_client
+            .post(format!("{}/admin/logout", app.address))
+            .header(header, value)
+            .form(&serde_json::json!({ "csrf_token": &csrf_token }))
+            .send()
+            .await
+            .expect("Failed to send post.");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 403);
+    }
+}
+
+#[tokio::test]
+async fn posts_from_our_own_pages_are_accepted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let csrf_token = app.get_csrf_token().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", app.address))
+        .header("Origin", &app.address)
+        .header("Referer", format!("{}/admin/dashboard", app.address))
+        .form(&serde_json::json!({ "csrf_token": &csrf_token }))
+        .send()
+        .await
+        .expect("Failed to send post.");
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
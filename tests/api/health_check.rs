@@ -18,3 +18,56 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn liveness_does_not_depend_on_anything() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_health("live").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn readiness_reports_every_dependency() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_health("ready").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ready");
+    for dependency in ["postgres", "redis", "migrations"] {
+        assert_eq!(body["checks"][dependency]["status"], "up");
+        assert!(body["checks"][dependency]["latency_ms"].is_number());
+    }
+}
+
+#[tokio::test]
+async fn a_database_behind_this_build_is_not_ready() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query(
+        "DELETE FROM _sqlx_migrations \
+        WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.get_health("ready").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["checks"]["migrations"]["status"], "down");
+    assert_eq!(body["checks"]["postgres"]["status"], "up");
+}
@@ -1,32 +1,81 @@
+use std::{io::Write, sync::Mutex};
+
 use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
+use fake::{
+    faker::{internet::en::SafeEmail, name::zh_tw::Name},
+    Fake,
+};
 use once_cell::sync::Lazy;
+use opentelemetry::{global, sdk::trace::TracerProvider, trace::TracerProvider as _};
 use reqwest::Url;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    configuration::{
+        get_configuration_with_secrets, DatabaseSettings, Settings, StubSecretProvider,
+    },
     email_client::EmailClient,
     issue_deliver_worker::{try_execute_task, ExecutionOutcome},
+    redaction::Redaction,
     startup::{get_connection_pool, Application},
     telemetry::{get_subscriber, init_subscriber},
+    tracking::IssueTracker,
 };
 
+/// Everything the app has logged in this test run, as it would reach the Bunyan output.
+static LOGS: Lazy<Mutex<Vec<u8>>> = Lazy::new(Mutex::default);
+
+/// Captures the logs, and echoes them to stdout if `TEST_LOG` is set.
+struct TestLog {
+    echo: bool,
+}
+
+impl Write for TestLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        LOGS.lock().unwrap().extend_from_slice(buf);
+        if self.echo {
+            std::io::stdout().write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stdout().flush()
+    }
+}
+
+/// The logs of every test so far, not just the calling one.
+pub fn captured_logs() -> String {
+    String::from_utf8_lossy(&LOGS.lock().unwrap()).into_owned()
+}
+
 static TRACING: Lazy<()> = Lazy::new(|| {
     let subscriber_name = String::from("zero2prod_integration_tests");
     let default_filter_level = String::from("debug");
 
-    if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
-        init_subscriber(subscriber);
-    } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
-        init_subscriber(subscriber);
-    }
+    // spans aren't exported anywhere, but still carry trace ids
+    let tracer_provider = TracerProvider::builder().build();
+    let tracer = tracer_provider.tracer("zero2prod_integration_tests");
+    global::set_tracer_provider(tracer_provider);
+
+    let echo = std::env::var("TEST_LOG").is_ok();
+    let subscriber = get_subscriber(
+        subscriber_name,
+        default_filter_level,
+        move || TestLog { echo },
+        Some(tracer),
+        Redaction::default(),
+    );
+    init_subscriber(subscriber);
 });
 
 pub struct ConfirmationLinks {
     pub html: reqwest::Url,
+    #[allow(dead_code)]
     pub plain_text: reqwest::Url,
 }
 
@@ -38,13 +87,14 @@ pub struct TestApp {
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
     pub email_client: EmailClient,
+    pub issue_tracker: IssueTracker,
 }
 
 impl TestApp {
     pub async fn dispatch_all_pending_emails(&self) {
         loop {
             if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
+                try_execute_task(&self.db_pool, &self.email_client, &self.issue_tracker)
                     .await
                     .unwrap()
             {
@@ -69,7 +119,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", self.address))
+            .post(format!("{}/login", self.address))
             .form(body)
             .send()
             .await
@@ -78,7 +128,7 @@ impl TestApp {
 
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to make get request")
@@ -89,15 +139,32 @@ impl TestApp {
 
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/logout", self.address))
+            .post(format!("{}/admin/logout", self.address))
+            .form(&self.with_csrf_token(&serde_json::json!({})).await)
             .send()
             .await
             .expect("Failed to send post.")
     }
 
+    pub async fn get_health(&self, probe: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/health/{}", &self.address, probe))
+            .send()
+            .await
+            .expect("Failed to send get.")
+    }
+
+    pub async fn get_metrics(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/metrics", &self.address))
+            .send()
+            .await
+            .expect("Failed to send get.")
+    }
+
     pub async fn get_root(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/", &self.address))
+            .get(format!("{}/", &self.address))
             .send()
             .await
             .expect("Failed to send get.")
@@ -105,7 +172,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to send get.")
@@ -119,7 +186,7 @@ impl TestApp {
 
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to send get.")
@@ -136,16 +203,72 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", self.address))
-            .form(body)
+            .post(format!("{}/admin/password", self.address))
+            .form(&self.with_csrf_token(body).await)
             .send()
             .await
             .expect("Failed to send post.")
     }
 
+    pub async fn get_sessions(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to send get.")
+    }
+
+    pub async fn get_sessions_html(&self) -> String {
+        let response = self.get_sessions().await;
+        assert_eq!(response.status().as_u16(), 200);
+        response.text().await.unwrap()
+    }
+
+    pub async fn post_revoke_session<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/sessions/revoke", self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to send post.")
+    }
+
+    pub async fn post_logout_everywhere(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/sessions/revoke-all", self.address))
+            .form(&self.with_csrf_token(&serde_json::json!({})).await)
+            .send()
+            .await
+            .expect("Failed to send post.")
+    }
+
+    /// Logs the test user in from a separate client, returning that client.
+    pub async fn login_on_another_device(&self, user_agent: &str) -> reqwest::Client {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .user_agent(user_agent)
+            .build()
+            .unwrap();
+        let response = client
+            .post(format!("{}/login", self.address))
+            .form(&serde_json::json!({
+                "username": &self.test_user.username,
+                "password": &self.test_user.password,
+            }))
+            .send()
+            .await
+            .expect("Failed to send post.");
+        assert_is_redirect_to(&response, "/admin/dashboard");
+        client
+    }
+
     pub async fn get_publish_newsletter(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/newsletters", &self.address))
+            .get(format!("{}/admin/newsletters", &self.address))
             .send()
             .await
             .expect("Failed to send get.")
@@ -157,9 +280,46 @@ impl TestApp {
         response.text().await.unwrap()
     }
 
+    /// The CSRF token of the current session, empty if we aren't logged in.
+    pub async fn get_csrf_token(&self) -> String {
+        let html = self.get_change_password().await.text().await.unwrap();
+        match html.split(r#"name="csrf_token" value=""#).nth(1) {
+            Some(token) => token[..token.find('"').unwrap()].to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// `body` as an admin form would submit it, with the CSRF token attached.
+    async fn with_csrf_token<Body>(&self, body: &Body) -> serde_json::Value
+    where
+        Body: serde::Serialize,
+    {
+        let mut body = serde_json::to_value(body).unwrap();
+        body["csrf_token"] = self.get_csrf_token().await.into();
+        body
+    }
+
+    /// Scrapes a fresh anti-abuse token from the subscribe form on the home page.
+    pub async fn get_form_token(&self) -> String {
+        let html = self.get_root().await.text().await.unwrap();
+        let token = html.split(r#"name="form_token" value=""#).nth(1).unwrap();
+        token[..token.find('"').unwrap()].to_string()
+    }
+
+    /// Submits `body` the way the subscribe form would, with a valid form token attached.
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        let form_token = format!("form_token={}", self.get_form_token().await);
+        let body = if body.is_empty() {
+            form_token
+        } else {
+            format!("{body}&{form_token}")
+        };
+        self.post_raw_subscriptions(body).await
+    }
+
+    pub async fn post_raw_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -202,8 +362,8 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/newsletters", &self.address))
-            .form(body)
+            .post(format!("{}/admin/newsletters", &self.address))
+            .form(&self.with_csrf_token(body).await)
             .send()
             .await
             .expect("Failed to send post.")
@@ -214,7 +374,7 @@ impl TestApp {
     //     Body: serde::Serialize,
     // {
     //     self.api_client
-    //         .post(&format!("{}/admin/password", self.address))
+    //         .post(format!("{}/admin/password", self.address))
     //         .form(body)
     //         .send()
     //         .await
@@ -223,15 +383,27 @@ impl TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Lets a test change the configuration before the app is built.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
 
     let configuration = {
-        let mut configuration = get_configuration().expect("Failed to load config.");
+        // every app signs its cookies and tokens with a key of its own, long enough for cookies
+        let hmac_secret = format!("{}{}", Uuid::new_v4(), Uuid::new_v4());
+        let secrets = StubSecretProvider::new([("application.hmac_secret", hmac_secret.as_str())]);
+        let mut configuration =
+            get_configuration_with_secrets(&secrets).expect("Failed to load config.");
         configuration.database.database_name = Uuid::new_v4().to_string();
         configuration.application.port = 0;
         configuration.email_client.base_url = email_server.uri();
+        configuration.subscriptions.min_form_fill_seconds = 0;
+        configuration.authentication.max_password_age_days = Some(90);
+        configure(&mut configuration);
         configuration
     };
 
@@ -249,7 +421,7 @@ pub async fn spawn_app() -> TestApp {
     // I don't like this very much but w/e
     let address = format!("http://127.0.0.1:{}", port);
 
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
 
     let api_client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -262,7 +434,12 @@ pub async fn spawn_app() -> TestApp {
         // db_pool: get_connection_pool(&configuration.database),
         db_pool,
         email_server,
-        email_client: configuration.email_client.client(),
+        email_client: configuration.email_client.client().unwrap(),
+        issue_tracker: IssueTracker::new(
+            configuration.application.base_url.clone(),
+            configuration.application.signing_keys(),
+            configuration.tracking.enabled,
+        ),
         port,
         test_user,
         api_client,
@@ -341,6 +518,44 @@ impl TestUser {
     }
 }
 
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body =
+        serde_urlencoded::to_string(serde_json::json!({"name": name, "email": email})).unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .named("Create unconfirmed subscriber")
+        // see https://docs.rs/wiremock/0.5.15/wiremock/struct.Mock.html#method.mount_as_scoped
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body).await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request).await
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_links = create_unconfirmed_subscriber(app).await;
+
+    reqwest::get(confirmation_links.html.to_string())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
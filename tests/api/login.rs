@@ -1,4 +1,9 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use actix_web::cookie::{Cookie, CookieJar, Key};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, Secret};
+use zero2prod::authentication::{bootstrap_admin, create_user};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -15,18 +20,81 @@ async fn an_error_flash_message_is_set_on_failure() {
 
     // Assert
     assert_is_redirect_to(&response, "/login");
+    let request_id = response.headers()["X-Request-Id"].to_str().unwrap();
 
     // Act 2
     let html_page = app.get_login_html().await;
 
     // Assert 2
-    assert!(html_page.contains(r#"<p><i>Authentication Failed</i></p>"#));
+    assert!(html_page.contains(&format!(
+        "<p><i>Authentication Failed (request id: {request_id})</i></p>"
+    )));
 
     // Act 3
     let html_page = app.get_login_html().await;
 
     // Assert 3
-    assert!(!html_page.contains(r#"<p><i>Authentication Failed</i></p>"#));
+    assert!(!html_page.contains("<p><i>Authentication Failed"));
+}
+
+#[tokio::test]
+async fn flash_messages_signed_with_a_previous_secret_are_shown() {
+    // Arrange
+    let previous_secret = "a".repeat(64);
+    let app = spawn_app_with(|c| {
+        c.application.previous_hmac_secrets = vec![Secret::new(previous_secret.clone())]
+    })
+    .await;
+    let messages =
+        serde_json::to_string(&[FlashMessage::error("Signed before the rotation")]).unwrap();
+    let mut jar = CookieJar::new();
+    jar.signed_mut(&Key::from(previous_secret.as_bytes()))
+        .add(Cookie::new("_flash", messages));
+    let cookie = jar.get("_flash").unwrap().encoded().stripped().to_string();
+
+    // Act
+    let html_page = app
+        .api_client
+        .get(format!("{}/login", &app.address))
+        .header("Cookie", cookie)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("<p><i>Signed before the rotation</i></p>"));
+}
+
+#[tokio::test]
+async fn flash_messages_are_escaped() {
+    // Arrange
+    let secret = "b".repeat(64);
+    let app = spawn_app_with(|c| c.application.hmac_secret = Secret::new(secret.clone())).await;
+    let messages =
+        serde_json::to_string(&[FlashMessage::error("<script>alert('hi')</script>")]).unwrap();
+    let mut jar = CookieJar::new();
+    jar.signed_mut(&Key::from(secret.as_bytes()))
+        .add(Cookie::new("_flash", messages));
+    let cookie = jar.get("_flash").unwrap().encoded().stripped().to_string();
+
+    // Act
+    let html_page = app
+        .api_client
+        .get(format!("{}/login", &app.address))
+        .header("Cookie", cookie)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("&lt;script&gt;"));
+    assert!(!html_page.contains("<script>alert"));
 }
 
 #[tokio::test]
@@ -47,3 +115,67 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+#[tokio::test]
+async fn admins_created_from_the_cli_can_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    create_user("gandalf", Secret::new("mellon".into()), false, &app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let login_body = serde_json::json!({
+        "username": "gandalf",
+        "password": "mellon",
+    });
+    let response = app.post_login(&login_body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn the_seeded_admin_password_is_replaced_at_startup() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let seeded_admin = sqlx::query!(
+        "SELECT password_hash, must_change_password FROM users WHERE username = 'admin'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Assert
+    assert!(!seeded_admin
+        .password_hash
+        .contains("WQFskHQ3dhvvIYpB5KVVlw$HBDCcv999PzA9Q3UJfn"));
+    assert!(seeded_admin.must_change_password);
+}
+
+#[tokio::test]
+async fn an_admin_is_bootstrapped_once_when_there_are_no_users() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!("DELETE FROM users")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act 1 - Bootstrap
+    let credential = bootstrap_admin(&app.db_pool).await.unwrap().unwrap();
+
+    // Act 2 - Login
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": credential.username,
+            "password": credential.password.expose_secret(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act 3 - Nothing left to bootstrap
+    assert!(bootstrap_admin(&app.db_pool).await.unwrap().is_none());
+}
@@ -1,6 +1,14 @@
 mod admin_dashboard;
 mod change_password;
+mod csrf;
 mod health_check;
 mod helpers;
 mod login;
+mod metrics;
+mod migrations;
 mod newsletters;
+mod request_id;
+mod security_headers;
+mod sessions;
+mod subscriptions;
+mod tracking;
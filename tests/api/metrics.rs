@@ -0,0 +1,158 @@
+use std::{net::TcpListener, time::Duration};
+
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::{
+    metrics::{record_email, EmailOutcome},
+    startup::MetricsServer,
+};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn get_metrics_text(app: &TestApp) -> String {
+    let response = app.get_metrics().await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn metrics_are_exposed_in_the_prometheus_text_format() {
+    // Arrange
+    let app = spawn_app().await;
+    // series only show up once they have been recorded
+    app.api_client
+        .get(format!("{}/health_check", app.address))
+        .send()
+        .await
+        .expect("Failed to send get.");
+
+    // Act
+    let response = app.get_metrics().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain; version=0.0.4"));
+    let text = response.text().await.unwrap();
+    assert!(text.contains("# TYPE http_requests_total counter"));
+    assert!(text.contains(r#"db_pool_connections{state="idle"}"#));
+    assert!(text.contains("issue_delivery_queue_depth 0"));
+}
+
+#[tokio::test]
+async fn requests_are_counted_per_route_pattern() {
+    // Arrange
+    let app = spawn_app().await;
+    app.api_client
+        .get(format!(
+            "{}/newsletters/{}/open",
+            app.address,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .expect("Failed to send get.");
+
+    // Act
+    let text = get_metrics_text(&app).await;
+
+    // Assert
+    assert!(text.contains(
+        r#"http_requests_total{method="GET",route="/newsletters/{newsletter_issue_id}/open","#
+    ));
+    assert!(text.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="/newsletters/{newsletter_issue_id}/open"}"#
+    ));
+}
+
+#[tokio::test]
+async fn password_checks_are_timed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+
+    // Act
+    let text = get_metrics_text(&app).await;
+
+    // Assert
+    assert!(text.contains("password_verification_duration_seconds_count"));
+}
+
+#[tokio::test]
+async fn queued_and_sent_emails_are_reported_per_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "New Title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    let issue_id = sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act 1
+    let text = get_metrics_text(&app).await;
+    assert!(text.contains("issue_delivery_queue_depth 1"));
+
+    // Act 2
+    app.dispatch_all_pending_emails().await;
+    let text = get_metrics_text(&app).await;
+    assert!(text.contains("issue_delivery_queue_depth 0"));
+    assert!(text.contains(&format!(
+        r#"newsletter_emails_total{{newsletter_issue_id="{issue_id}",outcome="sent"}} 1"#
+    )));
+}
+
+#[tokio::test]
+async fn a_worker_on_its_own_serves_its_metrics() {
+    // Arrange
+    let app = spawn_app().await;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let metrics_server =
+        MetricsServer::build(listener, app.db_pool.clone(), Duration::from_secs(1)).unwrap();
+    let address = format!("http://127.0.0.1:{}", metrics_server.port());
+    tokio::spawn(metrics_server.run_until_stopped());
+    // as the worker records its deliveries
+    let issue_id = Uuid::new_v4();
+    record_email(issue_id, EmailOutcome::Sent);
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{address}/metrics"))
+        .send()
+        .await
+        .expect("Failed to send get.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let text = response.text().await.unwrap();
+    assert!(text.contains("issue_delivery_queue_depth 0"));
+    assert!(text.contains(&format!(
+        r#"newsletter_emails_total{{newsletter_issue_id="{issue_id}",outcome="sent"}} 1"#
+    )));
+    let response = app
+        .api_client
+        .get(format!("{address}/health_check"))
+        .send()
+        .await
+        .expect("Failed to send get.");
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,63 @@
+use sqlx::{Connection, Executor, PgConnection, PgPool};
+use uuid::Uuid;
+use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::migrations::{run_migrations, MigrationError};
+
+async fn create_empty_database() -> DatabaseSettings {
+    let mut configuration = get_configuration()
+        .expect("Failed to load config.")
+        .database;
+    configuration.database_name = Uuid::new_v4().to_string();
+
+    let mut connection = PgConnection::connect_with(&configuration.without_db())
+        .await
+        .expect("Failed to connect to Postgres");
+    connection
+        .execute(format!(r#"CREATE DATABASE "{}";"#, configuration.database_name).as_str())
+        .await
+        .expect("Failed to create database");
+
+    configuration
+}
+
+#[tokio::test]
+async fn instances_migrating_side_by_side_do_not_race() {
+    // Arrange
+    let configuration = create_empty_database().await;
+
+    // Act
+    let (a, b) = tokio::join!(
+        run_migrations(&configuration),
+        run_migrations(&configuration)
+    );
+
+    // Assert
+    a.unwrap();
+    b.unwrap();
+}
+
+#[tokio::test]
+async fn migrating_fails_if_the_schema_is_newer_than_the_binary() {
+    // Arrange
+    let configuration = create_empty_database().await;
+    run_migrations(&configuration).await.unwrap();
+    let pool = PgPool::connect_with(configuration.with_db()).await.unwrap();
+    pool.execute(
+        "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+         VALUES (99991231235959, 'from the future', true, '\\x00', 0)",
+    )
+    .await
+    .unwrap();
+
+    // Act
+    let outcome = run_migrations(&configuration).await;
+
+    // Assert
+    assert!(matches!(
+        outcome,
+        Err(MigrationError::SchemaTooNew {
+            applied: 99991231235959,
+            ..
+        })
+    ));
+}
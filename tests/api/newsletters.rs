@@ -1,53 +1,15 @@
 use std::time::Duration;
 
-use fake::{
-    faker::{internet::en::SafeEmail, name::zh_tw::Name},
-    Fake,
-};
+use sqlx::postgres::PgListener;
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestApp};
-
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body =
-        serde_urlencoded::to_string(&serde_json::json!({"name": name, "email": email})).unwrap();
-
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .expect(1)
-        .named("Create unconfirmed subscriber")
-        // see https://docs.rs/wiremock/0.5.15/wiremock/struct.Mock.html#method.mount_as_scoped
-        .mount_as_scoped(&app.email_server)
-        .await;
-
-    app.post_subscriptions(body).await;
-
-    let email_request = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
-
-    app.get_confirmation_links(email_request).await
-}
-
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_links = create_unconfirmed_subscriber(app).await;
-
-    reqwest::get(confirmation_links.html.to_string())
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
-}
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+};
+use zero2prod::issue_deliver_worker::NEW_TASKS_CHANNEL;
 
 #[tokio::test]
 async fn must_login_to_post_newsletter() {
@@ -257,3 +219,96 @@ async fn concurrent_form_submission_is_handled_gracefully() {
     // Assert
     // Mock asserts only 1 POST was received
 }
+
+#[tokio::test]
+async fn publishing_an_issue_notifies_idle_workers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    let mut listener = PgListener::connect_with(&app.db_pool).await.unwrap();
+    listener.listen(NEW_TASKS_CHANNEL).await.unwrap();
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "New Title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    tokio::time::timeout(Duration::from_secs(5), listener.recv())
+        .await
+        .expect("No notification was sent for the new delivery tasks.")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn deliveries_carry_the_request_id_of_the_publishing_request() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/newsletters", &app.address))
+        .header("X-Request-Id", "support-ticket-1234")
+        .form(&serde_json::json!({
+            "title": "New Title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+            "csrf_token": app.get_csrf_token().await,
+        }))
+        .send()
+        .await
+        .expect("Failed to send post.");
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let request_id = sqlx::query_scalar!("SELECT request_id FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(request_id.as_deref(), Some("support-ticket-1234"));
+}
+
+#[tokio::test]
+async fn deliveries_carry_the_trace_of_the_publishing_request() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/newsletters", &app.address))
+        .header("traceparent", format!("00-{trace_id}-00f067aa0ba902b7-01"))
+        .form(&serde_json::json!({
+            "title": "New Title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+            "csrf_token": app.get_csrf_token().await,
+        }))
+        .send()
+        .await
+        .expect("Failed to send post.");
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let traceparent = sqlx::query_scalar!("SELECT traceparent FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(traceparent.starts_with(&format!("00-{trace_id}-")));
+}
@@ -0,0 +1,81 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn responses_carry_a_request_id() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let first = app.get_root().await;
+    let second = app.get_root().await;
+
+    // Assert
+    let first = first.headers()["X-Request-Id"].to_str().unwrap();
+    let second = second.headers()["X-Request-Id"].to_str().unwrap();
+    assert!(!first.is_empty());
+    assert_ne!(first, second);
+}
+
+#[tokio::test]
+async fn the_request_id_of_the_load_balancer_is_kept() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(&app.address)
+        .header("X-Request-Id", "lb-4bf92f35-77b3-4da6")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.headers()["X-Request-Id"], "lb-4bf92f35-77b3-4da6");
+}
+
+#[tokio::test]
+async fn malformed_request_ids_are_replaced() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(&app.address)
+        .header("X-Request-Id", "<script>alert(1)</script>")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let request_id = response.headers()["X-Request-Id"].to_str().unwrap();
+    assert!(!request_id.is_empty());
+    assert!(!request_id.contains('<'));
+}
+
+#[tokio::test]
+async fn error_pages_name_the_request_id() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions("name=Ursula&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    let request_id = response.headers()["X-Request-Id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(&format!("<code>{request_id}</code>")));
+    // the error itself is for our logs only
+    assert!(!html_page.contains("subscription_token"));
+}
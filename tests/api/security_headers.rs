@@ -0,0 +1,55 @@
+use crate::helpers::spawn_app;
+
+fn csp_nonce(response: &reqwest::Response) -> String {
+    let policy = response.headers()["Content-Security-Policy"]
+        .to_str()
+        .unwrap();
+    let nonce = policy.split("'nonce-").nth(1).unwrap();
+    nonce[..nonce.find('\'').unwrap()].to_string()
+}
+
+#[tokio::test]
+async fn responses_carry_the_security_headers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_root().await;
+
+    // Assert
+    let headers = response.headers();
+    assert!(headers.contains_key("Content-Security-Policy"));
+    assert_eq!(headers["X-Frame-Options"], "DENY");
+    assert_eq!(headers["Referrer-Policy"], "same-origin");
+    assert_eq!(headers["X-Content-Type-Options"], "nosniff");
+    // the test app is served over http
+    assert!(!headers.contains_key("Strict-Transport-Security"));
+}
+
+#[tokio::test]
+async fn redirects_to_the_login_page_carry_the_security_headers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    assert!(response.headers().contains_key("Content-Security-Policy"));
+    assert_eq!(response.headers()["X-Frame-Options"], "DENY");
+}
+
+#[tokio::test]
+async fn every_response_gets_a_fresh_csp_nonce() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let first = app.get_root().await;
+    let second = app.get_root().await;
+
+    // Assert
+    assert!(!csp_nonce(&first).is_empty());
+    assert_ne!(csp_nonce(&first), csp_nonce(&second));
+}
@@ -0,0 +1,193 @@
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn session_id_for(app: &TestApp, user_agent: &str) -> Uuid {
+    sqlx::query_scalar!(
+        "SELECT session_id FROM user_sessions WHERE user_agent = $1",
+        user_agent
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch session.")
+}
+
+async fn is_logged_in(app: &TestApp, client: &reqwest::Client) -> bool {
+    let response = client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .expect("Failed to send get.");
+    response.status().as_u16() == 200
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_your_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_sessions().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn active_sessions_are_listed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    app.login_on_another_device("a-phone-browser").await;
+
+    // Act
+    let html_page = app.get_sessions_html().await;
+
+    // Assert
+    assert!(html_page.contains("a-phone-browser"));
+    assert!(html_page.contains("This session"));
+    assert!(html_page.contains(&session_id_for(&app, "a-phone-browser").await.to_string()));
+}
+
+#[tokio::test]
+async fn sessions_record_the_connecting_address_rather_than_a_made_up_one() {
+    // Arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent("a-spoofing-browser")
+        .build()
+        .unwrap();
+
+    // Act
+    client
+        .post(format!("{}/login", app.address))
+        .header("X-Forwarded-For", "203.0.113.7")
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .expect("Failed to send post.");
+
+    // Assert
+    let ip = sqlx::query_scalar!(
+        "SELECT ip FROM user_sessions WHERE user_agent = $1",
+        "a-spoofing-browser"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch session.");
+    assert_eq!(ip, "127.0.0.1");
+}
+
+#[tokio::test]
+async fn a_revoked_session_is_logged_out() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let other_device = app.login_on_another_device("a-phone-browser").await;
+    assert!(is_logged_in(&app, &other_device).await);
+
+    // Act
+    let response = app
+        .post_revoke_session(&serde_json::json!({
+            "session_id": session_id_for(&app, "a-phone-browser").await,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("<p><i>The session has been revoked.</i></p>"));
+    assert!(!html_page.contains("a-phone-browser"));
+    assert!(!is_logged_in(&app, &other_device).await);
+    assert!(is_logged_in(&app, &app.api_client).await);
+}
+
+#[tokio::test]
+async fn revoking_an_unknown_session_changes_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let other_device = app.login_on_another_device("a-phone-browser").await;
+
+    // Act
+    let response = app
+        .post_revoke_session(&serde_json::json!({ "session_id": Uuid::new_v4() }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("<p><i>The session had already ended. (request id: "));
+    assert!(is_logged_in(&app, &other_device).await);
+}
+
+#[tokio::test]
+async fn logging_out_everywhere_ends_every_session() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let other_device = app.login_on_another_device("a-phone-browser").await;
+
+    // Act
+    let response = app.post_logout_everywhere().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You have been logged out of all sessions.</i></p>"));
+    assert!(!is_logged_in(&app, &app.api_client).await);
+    assert!(!is_logged_in(&app, &other_device).await);
+}
+
+#[tokio::test]
+async fn changing_password_ends_all_other_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let other_device = app.login_on_another_device("a-phone-browser").await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+    assert!(is_logged_in(&app, &app.api_client).await);
+    assert!(!is_logged_in(&app, &other_device).await);
+}
+
+#[tokio::test]
+async fn sessions_past_the_absolute_timeout_are_ended_with_a_message() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    sqlx::query!("UPDATE user_sessions SET created_at = now() - interval '30 days'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to age the session.");
+
+    // Act - Part 1 - Visit the admin area
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your session has expired. Please log in again.</i></p>"));
+
+    // Act - Part 3 - The session is gone for good
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(!app.get_login_html().await.contains("expired"));
+}
@@ -1,9 +1,12 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{captured_logs, spawn_app, spawn_app_with, TestApp};
+
+use uuid::Uuid;
 
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
+use zero2prod::{domain::EmailValidator, routes::backfill_canonical_emails};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -237,3 +240,461 @@ async fn subscribe_fails_if_subscription_tokens_table_is_borked() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribe_rejects_submissions_with_the_honeypot_filled_in() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_rejects_submissions_without_a_valid_form_token() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let test_cases = vec![
+        ("name=le%20guin&email=ursula_le_guin%40gmail.com", "missing"),
+        (
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token=1670000000.0011223344556677.abcdef",
+            "forged",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_raw_subscriptions(body.into()).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject a {} form token",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribe_rejects_a_form_token_that_was_already_used() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let form_token = app.get_form_token().await;
+    let first = format!("name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={form_token}");
+    let replay = format!("name=le%20guin&email=ursula%40gmail.com&form_token={form_token}");
+
+    // Act
+    let first = app.post_raw_subscriptions(first).await;
+    let replay = app.post_raw_subscriptions(replay).await;
+
+    // Assert
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(400, replay.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_rate_limits_attempts_from_the_same_ip() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(10)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for i in 0..10 {
+        let body = format!("name=le%20guin&email=ursula_{i}%40gmail.com");
+        let response = app.post_subscriptions(body).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_made_up_forwarded_for_does_not_get_around_the_ip_rate_limit() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(10)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let mut statuses = vec![];
+    for i in 0..11 {
+        let body = format!(
+            "name=le%20guin&email=ursula_{i}%40gmail.com&form_token={}",
+            app.get_form_token().await
+        );
+        let response = app
+            .api_client
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", format!("203.0.113.{i}"))
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        statuses.push(response.status().as_u16());
+    }
+
+    // Assert
+    assert_eq!(statuses[..10], [200; 10]);
+    assert_eq!(statuses[10], 429);
+}
+
+#[tokio::test]
+async fn variants_of_an_address_share_its_rate_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_validation.canonicalize_gmail = true).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let mut statuses = vec![];
+    for email in [
+        "ursula_le_guin%40gmail.com",
+        "Ursula_Le_Guin%40gmail.com",
+        "ursula_le_guin%2Bone%40gmail.com",
+        "ursula_le_gu.in%2Btwo%40googlemail.com",
+    ] {
+        let body = format!("name=le%20guin&email={email}");
+        statuses.push(app.post_subscriptions(body).await.status().as_u16());
+    }
+
+    // Assert
+    assert_eq!(statuses, [200, 200, 200, 429]);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_disposable_email_providers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = "name=le%20guin&email=ursula_le_guin%40mailinator.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn rejected_addresses_do_not_reach_the_logs() {
+    // Arrange
+    let app = spawn_app().await;
+    let local_part = Uuid::new_v4().to_string();
+    let addresses = [
+        format!("{local_part}%40mailinator.com"),
+        format!("{local_part}-at-nowhere"),
+    ];
+
+    for address in addresses {
+        // Act
+        let request_id = Uuid::new_v4().to_string();
+        let body = format!(
+            "name=le%20guin&email={address}&form_token={}",
+            app.get_form_token().await
+        );
+        let response = app
+            .api_client
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Request-Id", &request_id)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(400, response.status().as_u16());
+        let logs = captured_logs();
+        assert!(logs.contains(&request_id));
+        assert!(!logs.contains(&local_part));
+    }
+}
+
+#[tokio::test]
+async fn gmail_canonicalization_dedupes_without_rewriting_the_address() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_validation.canonicalize_gmail = true).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=First.Last%2Bnews%40googlemail.com".into())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let response = app
+        .post_subscriptions("name=le%20guin&email=firstlast%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email, canonical_email FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "First.Last+news@googlemail.com");
+    assert_eq!(saved[0].canonical_email, "firstlast@gmail.com");
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "First.Last+news@googlemail.com");
+}
+
+/// Sets every canonical form back to what it was before `canonicalize_gmail` was switched on.
+async fn forget_gmail_canonicalization(app: &TestApp) {
+    sqlx::query!("UPDATE subscriptions SET canonical_email = lower(email)")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to reset the canonical emails.");
+}
+
+async fn canonical_emails(app: &TestApp) -> Vec<String> {
+    sqlx::query_scalar!("SELECT canonical_email FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.")
+}
+
+#[tokio::test]
+async fn subscribers_from_before_gmail_canonicalization_can_subscribe_again() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_validation.canonicalize_gmail = true).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=Ursula.Le.Guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    forget_gmail_canonicalization(&app).await;
+
+    // Act
+    backfill_canonical_emails(&app.db_pool, &EmailValidator::new(true, "", None))
+        .await
+        .unwrap();
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(canonical_emails(&app).await, ["ursulaleguin@gmail.com"]);
+}
+
+#[tokio::test]
+async fn backfilling_keeps_the_old_canonical_form_when_the_new_one_is_taken() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    // two subscribers while the setting was off, one and the same once it is on
+    for email in ["Ursula.Le.Guin%40gmail.com", "ursulaleguin%40gmail.com"] {
+        let body = format!("name=le%20guin&email={email}");
+        app.post_subscriptions(body).await;
+    }
+
+    // Act
+    backfill_canonical_emails(&app.db_pool, &EmailValidator::new(true, "", None))
+        .await
+        .unwrap();
+    let body = "name=le%20guin&email=Ursula.Le.Guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        canonical_emails(&app).await,
+        ["ursula.le.guin@gmail.com", "ursulaleguin@gmail.com"]
+    );
+}
+
+#[tokio::test]
+async fn subscribing_twice_with_a_different_case_keeps_a_single_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40Gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribe_sends_the_confirmation_email_in_the_chosen_language() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=fr";
+    app.post_subscriptions(body.into()).await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Bienvenue !");
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("pour confirmer votre abonnement"));
+
+    let saved = sqlx::query!("SELECT locale FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.locale, "fr");
+}
+
+#[tokio::test]
+async fn the_confirmation_page_uses_the_language_negotiated_at_signup() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+        app.get_form_token().await
+    );
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept-Language", "de-AT,de;q=0.9,en;q=0.5")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send post.");
+
+    // Act
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request).await;
+    let response = reqwest::get(confirmation_links.html)
+        .await
+        .expect("Failed to send request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"<html lang="de">"#));
+    assert!(html_page.contains("Abonnement bestätigt"));
+}
+
+#[tokio::test]
+async fn the_subscribe_form_follows_accept_language() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let html_page = app
+        .api_client
+        .get(format!("{}/", &app.address))
+        .header("Accept-Language", "fr")
+        .send()
+        .await
+        .expect("Failed to send get.")
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains(r#"<html lang="fr">"#));
+    assert!(html_page.contains("Bienvenue sur notre newsletter !"));
+    assert!(html_page.contains(r#"<option value="fr" selected>Français</option>"#));
+}
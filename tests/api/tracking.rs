@@ -0,0 +1,169 @@
+use reqwest::Url;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::tracking::extract_links;
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+struct TrackingLinks {
+    open: Url,
+    clicks: Vec<Url>,
+}
+
+async fn publish_and_deliver(app: &TestApp, tracking_enabled: bool) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "New Title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": r#"<p>Read <a href="https://example.com/post">this</a></p>"#,
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+            "tracking_enabled": tracking_enabled,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    body["HtmlBody"].as_str().unwrap().to_string()
+}
+
+fn get_tracking_links(app: &TestApp, html: &str) -> TrackingLinks {
+    let with_port = |s: &str| {
+        let mut url = Url::parse(s).unwrap();
+        url.set_port(Some(app.port)).unwrap();
+        url
+    };
+
+    let open = html.split(r#"<img src=""#).nth(1).unwrap();
+    let open = open[..open.find('"').unwrap()].replace("&amp;", "&");
+
+    TrackingLinks {
+        open: with_port(&open),
+        clicks: extract_links(html).iter().map(|l| with_port(l)).collect(),
+    }
+}
+
+fn query_param(url: &Url, name: &str) -> String {
+    url.query_pairs()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.into_owned())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn untracked_issues_are_delivered_unchanged() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    // Act
+    let html = publish_and_deliver(&app, false).await;
+
+    // Assert
+    assert_eq!(
+        html,
+        r#"<p>Read <a href="https://example.com/post">this</a></p>"#
+    );
+}
+
+#[tokio::test]
+async fn opens_and_clicks_on_tracked_issues_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+    let html = publish_and_deliver(&app, true).await;
+    let links = get_tracking_links(&app, &html);
+    assert_eq!(links.clicks.len(), 1);
+
+    // Act 1 - Open the email
+    let response = app.api_client.get(links.open).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+
+    // Act 2 - Click the link
+    let response = app
+        .api_client
+        .get(links.clicks[0].clone())
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "https://example.com/post");
+
+    // Assert
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("<td>New Title</td>"));
+    assert!(html_page.contains("<td>1 (1)</td><td>1 (1)</td>"));
+}
+
+#[tokio::test]
+async fn clicks_are_rejected_if_the_destination_was_tampered_with() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+    let html = publish_and_deliver(&app, true).await;
+    let mut click = get_tracking_links(&app, &html).clicks.remove(0);
+    let tag = query_param(&click, "tag");
+    let subscriber_id = query_param(&click, "subscriber_id");
+
+    // Act
+    click
+        .query_pairs_mut()
+        .clear()
+        .append_pair("subscriber_id", &subscriber_id)
+        .append_pair("url", "https://evil.example.com")
+        .append_pair("tag", &tag);
+    let response = app.api_client.get(click).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn clicks_are_rejected_if_the_issue_never_contained_the_destination() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+    let html = publish_and_deliver(&app, true).await;
+    let click = get_tracking_links(&app, &html).clicks.remove(0);
+    let issue_id = click
+        .path_segments()
+        .unwrap()
+        .nth(1)
+        .unwrap()
+        .parse()
+        .unwrap();
+    let subscriber_id = query_param(&click, "subscriber_id").parse().unwrap();
+
+    // Act - a correctly signed link to somewhere the issue doesn't point
+    let mut forged = Url::parse(&app.issue_tracker.click_url(
+        issue_id,
+        subscriber_id,
+        "https://evil.example.com",
+    ))
+    .unwrap();
+    forged.set_port(Some(app.port)).unwrap();
+    let response = app.api_client.get(forged).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}